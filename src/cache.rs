@@ -0,0 +1,148 @@
+use std::{collections::hash_map::DefaultHasher, fs, hash::{Hash, Hasher}, path::Path, time::UNIX_EPOCH};
+
+use mdbook::errors::Error;
+use serde_json::{json, Map, Value};
+
+use crate::preprocessor::{CodeBook, CodeTag, Location, SourceFile, SourceLine};
+
+/// Bumped whenever the parser or the cache layout changes, to drop stale caches.
+const CACHE_VERSION: u32 = 4;
+
+/// Parsed source files kept on disk between builds, so that only the files
+/// modified since the previous build are parsed again.
+///
+/// Entries are keyed by path and stamped with the modification time and size
+/// of the file. The whole cache is dropped when the code tags of the book or
+/// the parser settings change.
+pub(crate) struct SourceCache<'a> {
+    code_book: &'a CodeBook,
+    fingerprint: String,
+    previous: Map<String, Value>,
+    current: Map<String, Value>,
+    changed: bool
+}
+
+/// What identifies a given version of a source file.
+pub(crate) fn file_stamp(path: &Path) -> Option<Value> {
+    let metadata = fs::metadata(path).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(json!([modified.as_secs(), modified.subsec_nanos(), metadata.len()]))
+}
+
+fn fingerprint(code_book: &CodeBook, settings: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    CACHE_VERSION.hash(&mut hasher);
+    settings.hash(&mut hasher);
+    for chapter in &code_book.chapters {
+        chapter.name.hash(&mut hasher);
+        chapter.code_tags.hash(&mut hasher);
+    }
+    format!("{:016x}", hasher.finish())
+}
+
+impl<'a> SourceCache<'a> {
+    /// Loads the cache stored in `path`, starting afresh when it is missing,
+    /// unreadable or was built for other code tags or `settings`.
+    pub(crate) fn load(path: &Path, code_book: &'a CodeBook, settings: &str) -> Self {
+        let fingerprint = fingerprint(code_book, settings);
+        let previous = fs::read(path).ok()
+            .and_then(|content| serde_json::from_slice::<Value>(&content).ok())
+            .filter(|cache| cache["fingerprint"] == fingerprint.as_str())
+            .and_then(|mut cache| match cache["files"].take() {
+                Value::Object(files) => Some(files),
+                _ => None
+            })
+            .unwrap_or_default();
+        SourceCache { code_book, fingerprint, previous, current: Map::new(), changed: false }
+    }
+
+    /// Writes the cache, unless nothing changed since it was loaded. This
+    /// avoids endless rebuilds when the cache file is in a watched directory.
+    pub(crate) fn save(&self, path: &Path) -> Result<(), Error> {
+        // Entries still in `previous` belong to deleted files.
+        if !self.changed && self.previous.is_empty() && path.exists() {
+            return Ok(())
+        }
+        let cache = json!({ "fingerprint": self.fingerprint, "files": self.current });
+        fs::write(path, serde_json::to_vec(&cache)?)?;
+        Ok(())
+    }
+
+    /// Returns the cached parse of `relative_path` if it is still up to date.
+    pub(crate) fn get(&self, relative_path: &Path, stamp: &Value) -> Option<SourceFile<'a>> {
+        let entry = self.previous.get(relative_path.to_str()?)?;
+        if &entry["stamp"] != stamp {
+            return None
+        }
+        let locations = entry["locations"].as_array()?.iter()
+            .map(location_from_json)
+            .collect::<Option<Vec<_>>>()?;
+        let lines = entry["lines"].as_array()?.iter().map(|line| {
+            Some(SourceLine {
+                content: String::from(line[0].as_str()?),
+                location: locations.get(line[1].as_u64()? as usize)?.clone(),
+                start: self.code_tag(&line[2])?,
+                end: if line[3].is_null() { None } else { Some(self.code_tag(&line[3])?) }
+            })
+        }).collect::<Option<Vec<_>>>()?;
+        Some(SourceFile { path: relative_path.to_path_buf(), lines })
+    }
+
+    /// Keeps the entry of a file which was up to date for the next build.
+    pub(crate) fn keep(&mut self, relative_path: &Path) {
+        let key = relative_path.to_string_lossy();
+        if let Some(entry) = self.previous.remove(key.as_ref()) {
+            self.current.insert(key.into_owned(), entry);
+        }
+    }
+
+    pub(crate) fn insert(&mut self, source_file: &SourceFile, stamp: Value) {
+        let mut locations: Vec<&Location> = Vec::new();
+        let lines: Vec<Value> = source_file.lines.iter().map(|line| {
+            // Consecutive lines mostly share their location.
+            if locations.last() != Some(&&line.location) {
+                locations.push(&line.location);
+            }
+            json!([line.content, locations.len() - 1, code_tag_to_json(line.start), line.end.map(code_tag_to_json)])
+        }).collect();
+        let locations: Vec<Value> = locations.into_iter().map(location_to_json).collect();
+        self.changed = true;
+        self.current.insert(source_file.path.to_string_lossy().into_owned(), json!({
+            "stamp": stamp,
+            "locations": locations,
+            "lines": lines
+        }));
+    }
+
+    fn code_tag(&self, value: &Value) -> Option<&'a CodeTag> {
+        let chapter = self.code_book.chapters.get(value[0].as_u64()? as usize)?;
+        chapter.code_tags.iter().find(|t| Some(t.index as u64) == value[1].as_u64())
+    }
+}
+
+fn code_tag_to_json(code_tag: &CodeTag) -> Value {
+    json!([code_tag.chapter, code_tag.index])
+}
+
+fn location_to_json(location: &Location) -> Value {
+    json!({
+        "parent": location.parent.as_deref().map(location_to_json),
+        "kind": location.kind,
+        "name": location.name,
+        "is_function_declaration": location.is_function_declaration,
+        "signature": location.signature
+    })
+}
+
+fn location_from_json(value: &Value) -> Option<Location> {
+    Some(Location {
+        parent: match &value["parent"] {
+            Value::Null => None,
+            parent => Some(Box::new(location_from_json(parent)?))
+        },
+        kind: String::from(value["kind"].as_str()?),
+        name: value["name"].as_str().map(String::from),
+        is_function_declaration: value["is_function_declaration"].as_bool()?,
+        signature: value["signature"].as_str().map(String::from)
+    })
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+
+use toml::Value;
+
+use crate::{language::LanguageBackend, preprocessor::CaptionPart};
+
+/// The English templates, used for every key the configuration leaves out.
+const ENGLISH: &[(&str, &str)] = &[
+    ("new-file", "create new file"),
+    ("top-of-file", "add to top of file"),
+    ("in-function", "in {name}({params})"),
+    ("changed-function", "{kind} {name}({params})"),
+    ("in-kind", "in {kind} {name}"),
+    ("after-function", "add after {name}({params})"),
+    ("after-kind", "add after {kind} {name}"),
+];
+
+/// The templates of the location captions, such as `in {kind} {name}`, where
+/// `{kind}` stands for the kind of a location, `{name}` for its name and
+/// `{params}` for the parameter types of overloaded functions.
+///
+/// Templates come from the `captions` table of the configuration, whose
+/// subtables override them for the book language, as in `captions.fr`.
+#[derive(Debug, Clone, Default)]
+pub struct Captions {
+    templates: HashMap<String, String>,
+    languages: HashMap<String, HashMap<String, String>>,
+    /// The language of the book, picking the overrides to use.
+    pub language: Option<String>
+}
+
+fn read_templates(table: &toml::map::Map<String, Value>, path: &str, languages: Option<&mut HashMap<String, HashMap<String, String>>>) -> HashMap<String, String> {
+    let mut templates = HashMap::new();
+    let mut languages = languages;
+    for (key, value) in table {
+        match (value, languages.as_deref_mut()) {
+            (Value::String(template), _) => {
+                templates.insert(key.clone(), template.clone());
+            }
+            (Value::Table(language), Some(languages)) => {
+                let overrides = read_templates(language, &format!("{}.{}", path, key), None);
+                languages.insert(key.clone(), overrides);
+            }
+            _ => log::error!("field `{}.{}` has invalid data type (expected string)", path, key)
+        }
+    }
+    templates
+}
+
+impl Captions {
+    pub fn from_table(table: &toml::map::Map<String, Value>) -> Self {
+        let mut languages = HashMap::new();
+        let templates = read_templates(table, "captions", Some(&mut languages));
+        Captions { templates, languages, language: None }
+    }
+
+    /// The template of `key`, for the book language if it overrides it.
+    pub fn template(&self, key: &str) -> Option<&str> {
+        self.language.as_ref()
+            .and_then(|language| self.languages.get(language))
+            .and_then(|overrides| overrides.get(key))
+            .or_else(|| self.templates.get(key))
+            .map(String::as_str)
+            .or_else(|| ENGLISH.iter().find(|(k, _)| *k == key).map(|(_, template)| *template))
+    }
+
+    /// The word naming the location kind `kind`, as in `method`: the
+    /// `kind-method` template if any, else the wording of `language`.
+    pub fn kind(&self, language: &dyn LanguageBackend, kind: &str) -> String {
+        let word = self.template(&format!("kind-{}", kind)).unwrap_or_else(|| language.kind_word(kind));
+        String::from(word)
+    }
+
+    /// Fills the template of `key` with the word `kind` and the parameter
+    /// types `params`, the name becoming a code part.
+    pub fn fill(&self, key: &str, kind: &str, name: &str, params: &str) -> Vec<CaptionPart> {
+        let template = self.template(key).unwrap_or(key).replace("{kind}", kind).replace("{params}", params);
+        let mut result = Vec::new();
+        let mut pieces = template.split("{name}").peekable();
+        while let Some(piece) = pieces.next() {
+            if !piece.is_empty() {
+                result.push(CaptionPart::Text(String::from(piece)));
+            }
+            if pieces.peek().is_some() {
+                result.push(CaptionPart::Code(String::from(name)));
+            }
+        }
+        result
+    }
+}
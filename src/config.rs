@@ -2,28 +2,133 @@ use std::path::PathBuf;
 
 use toml::Value;
 
-#[derive(Default)]
+use crate::captions::Captions;
+
+/// The LaTeX package typesetting the code of the `latex` renderer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LatexPackage {
+    Listings,
+    Minted
+}
+
 pub struct Configuration {
     pub src_root: PathBuf,
+    /// File caching the parsed sources between builds, `None` when disabled.
+    pub cache_file: Option<PathBuf>,
+    /// Decode source lines which are not valid UTF-8 as Latin-1, instead of
+    /// replacing their invalid bytes.
+    pub latin1_fallback: bool,
+    /// Expand tabs in source lines to this many columns, tabs are kept as is
+    /// when `None`.
+    pub tab_width: Option<usize>,
+    /// Hide the location captions of the snippets which do not say otherwise.
+    pub no_location: bool,
+    /// Add a link to itself next to each snippet.
+    pub permalinks: bool,
+    pub latex_package: LatexPackage,
+    pub captions: Captions,
+    /// Show the parameter types of overloaded functions in captions.
+    pub overload_signatures: bool,
     // TODO source file pattern
 }
 
-// TODO implements defaults manually to have custom default values
+impl Default for Configuration {
+    fn default() -> Self {
+        Configuration {
+            src_root: PathBuf::from("../src"),
+            // Not in the build directory, which the html renderer empties on each build.
+            cache_file: Some(PathBuf::from(".codetags-cache.json")),
+            latin1_fallback: false,
+            tab_width: None,
+            no_location: false,
+            permalinks: false,
+            latex_package: LatexPackage::Listings,
+            captions: Captions::default(),
+            overload_signatures: false,
+        }
+    }
+}
 
 impl TryFrom<&toml::map::Map<String, toml::Value>> for Configuration {
     type Error = &'static str;
-    
+
     fn try_from(value: &toml::map::Map<String, toml::Value>) -> Result<Self, Self::Error> {
-        let default_src: PathBuf = PathBuf::from("../src");
+        let default = Configuration::default();
         Ok(Configuration {
             src_root: match value.get("src-root") {
                 Some(Value::String(src_root)) => PathBuf::from(src_root),
-                None => default_src,
+                None => default.src_root,
                 _ => {
                     log::error!("field `src-root` has invalid data type (expected string)");
-                    default_src
+                    default.src_root
+                }
+            },
+            cache_file: match value.get("cache-file") {
+                Some(Value::String(cache_file)) => Some(PathBuf::from(cache_file)),
+                Some(Value::Boolean(false)) => None,
+                Some(Value::Boolean(true)) | None => default.cache_file,
+                _ => {
+                    log::error!("field `cache-file` has invalid data type (expected string or boolean)");
+                    default.cache_file
+                }
+            },
+            latin1_fallback: match value.get("latin1-fallback") {
+                Some(Value::Boolean(latin1_fallback)) => *latin1_fallback,
+                None => default.latin1_fallback,
+                _ => {
+                    log::error!("field `latin1-fallback` has invalid data type (expected boolean)");
+                    default.latin1_fallback
+                }
+            },
+            tab_width: match value.get("tab-width") {
+                Some(Value::Integer(tab_width)) if *tab_width > 0 => Some(*tab_width as usize),
+                None => default.tab_width,
+                _ => {
+                    log::error!("field `tab-width` has invalid data type (expected positive integer)");
+                    default.tab_width
+                }
+            },
+            no_location: match value.get("no-location") {
+                Some(Value::Boolean(no_location)) => *no_location,
+                None => default.no_location,
+                _ => {
+                    log::error!("field `no-location` has invalid data type (expected boolean)");
+                    default.no_location
+                }
+            },
+            permalinks: match value.get("permalinks") {
+                Some(Value::Boolean(permalinks)) => *permalinks,
+                None => default.permalinks,
+                _ => {
+                    log::error!("field `permalinks` has invalid data type (expected boolean)");
+                    default.permalinks
+                }
+            },
+            latex_package: match value.get("latex-package").and_then(Value::as_str) {
+                Some("listings") => LatexPackage::Listings,
+                Some("minted") => LatexPackage::Minted,
+                None if value.get("latex-package").is_none() => default.latex_package,
+                _ => {
+                    log::error!("field `latex-package` has invalid data (expected \"listings\" or \"minted\")");
+                    default.latex_package
+                }
+            },
+            captions: match value.get("captions") {
+                Some(Value::Table(captions)) => Captions::from_table(captions),
+                None => default.captions,
+                _ => {
+                    log::error!("field `captions` has invalid data type (expected table)");
+                    default.captions
+                }
+            },
+            overload_signatures: match value.get("overload-signatures") {
+                Some(Value::Boolean(overload_signatures)) => *overload_signatures,
+                None => default.overload_signatures,
+                _ => {
+                    log::error!("field `overload-signatures` has invalid data type (expected boolean)");
+                    default.overload_signatures
                 }
             },
         })
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,241 @@
+use std::fmt::Write;
+
+use crate::preprocessor::{CodeBook, CodeTag, SourceFile, SourceLine};
+
+const CONTEXT_LINES: usize = 3;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Change {
+    Keep,
+    Remove,
+    Add
+}
+
+struct Edit<'a> {
+    change: Change,
+    content: &'a str,
+    // 1-based line numbers of the line in the old and new snapshots, or of
+    // the line just before it when it is absent from that snapshot.
+    old_line: usize,
+    new_line: usize
+}
+
+fn is_present(line: &SourceLine, at: Option<&CodeTag>) -> bool {
+    at.is_some_and(|tag| line.is_present_at(tag))
+}
+
+/// Computes the unified diff of all source files between the book points
+/// `from` and `to`. A missing `from` stands for the empty source tree.
+pub fn unified_diff(source_files: &[SourceFile], from: Option<&CodeTag>, to: &CodeTag) -> String {
+    let mut result = String::new();
+    for source_file in source_files {
+        file_diff(&mut result, source_file, from, to);
+    }
+    result
+}
+
+fn file_diff(result: &mut String, source_file: &SourceFile, from: Option<&CodeTag>, to: &CodeTag) {
+    let mut edits = Vec::new();
+    let mut old_line = 0;
+    let mut new_line = 0;
+    for line in &source_file.lines {
+        let change = match (is_present(line, from), line.is_present_at(to)) {
+            (true, true) => Change::Keep,
+            (true, false) => Change::Remove,
+            (false, true) => Change::Add,
+            (false, false) => continue
+        };
+        if change != Change::Add {
+            old_line += 1;
+        }
+        if change != Change::Remove {
+            new_line += 1;
+        }
+        edits.push(Edit { change, content: &line.content, old_line, new_line });
+    }
+
+    if edits.iter().all(|e| e.change == Change::Keep) {
+        return
+    }
+
+    let path = source_file.path.to_string_lossy().replace('\\', "/");
+    writeln!(result, "diff --git a/{} b/{}", path, path).unwrap();
+    if old_line == 0 {
+        writeln!(result, "new file mode 100644\n--- /dev/null\n+++ b/{}", path).unwrap();
+    } else if new_line == 0 {
+        writeln!(result, "deleted file mode 100644\n--- a/{}\n+++ /dev/null", path).unwrap();
+    } else {
+        writeln!(result, "--- a/{}\n+++ b/{}", path, path).unwrap();
+    }
+
+    for (first, last) in hunks(&edits) {
+        let hunk = &edits[first..last];
+        let old_count = hunk.iter().filter(|e| e.change != Change::Add).count();
+        let new_count = hunk.iter().filter(|e| e.change != Change::Remove).count();
+        // An empty range is numbered after the line preceding it.
+        let old_start = hunk.iter().find(|e| e.change != Change::Add).map_or(hunk[0].old_line, |e| e.old_line);
+        let new_start = hunk.iter().find(|e| e.change != Change::Remove).map_or(hunk[0].new_line, |e| e.new_line);
+        writeln!(result, "@@ -{} +{} @@", range(old_start, old_count), range(new_start, new_count)).unwrap();
+        for edit in hunk {
+            let marker = match edit.change {
+                Change::Keep => ' ',
+                Change::Remove => '-',
+                Change::Add => '+'
+            };
+            writeln!(result, "{}{}", marker, edit.content).unwrap();
+        }
+    }
+}
+
+fn range(start: usize, count: usize) -> String {
+    if count == 1 {
+        start.to_string()
+    } else {
+        format!("{},{}", start, count)
+    }
+}
+
+/// Groups changed lines into hunks surrounded by `CONTEXT_LINES` lines of
+/// context, merging hunks whose context would overlap.
+fn hunks(edits: &[Edit]) -> Vec<(usize, usize)> {
+    let mut result: Vec<(usize, usize)> = Vec::new();
+    for (i, _) in edits.iter().enumerate().filter(|(_, e)| e.change != Change::Keep) {
+        let first = i.saturating_sub(CONTEXT_LINES);
+        let last = (i + 1 + CONTEXT_LINES).min(edits.len());
+        match result.last_mut() {
+            Some(hunk) if hunk.1 >= first => hunk.1 = last,
+            _ => result.push((first, last))
+        }
+    }
+    result
+}
+
+/// Formats the changes from `from` to `to` as a mailbox of patches, one per
+/// chapter, that `git am` can apply in order.
+pub fn patch_series(code_book: &CodeBook, source_files: &[SourceFile], from: Option<&CodeTag>, to: &CodeTag) -> String {
+    let first_chapter = from.map_or(0, |f| f.chapter);
+    let steps: Vec<&CodeTag> = code_book.chapters[first_chapter..=to.chapter].iter()
+        .filter_map(|c| c.code_tags.last())
+        .map(|last| if to.is_before(last) { to } else { last })
+        .filter(|end| from.is_none_or(|f| f.is_before(end)))
+        .collect();
+
+    let mut patches = Vec::new();
+    let mut previous = from;
+    for end in steps {
+        let diff = unified_diff(source_files, previous, end);
+        previous = Some(end);
+        if !diff.is_empty() {
+            patches.push((end, diff));
+        }
+    }
+
+    let mut result = String::new();
+    for (index, (end, diff)) in patches.iter().enumerate() {
+        writeln!(result, "From 0000000000000000000000000000000000000000 Mon Sep 17 00:00:00 2001").unwrap();
+        writeln!(result, "From: mdbook-codetags <mdbook-codetags@localhost>").unwrap();
+        writeln!(result, "Subject: [PATCH {}/{}] {}/{}\n\n---", index + 1, patches.len(),
+            code_book.chapters[end.chapter].name, end.name).unwrap();
+        result.push_str(diff);
+        result.push_str("-- \n\n");
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::{options::CodeTagOptions, preprocessor::{Chapter, Location}};
+
+    fn code_tag(chapter: usize, name: &str, index: u32) -> CodeTag {
+        CodeTag { chapter, name: String::from(name), index, options: CodeTagOptions::default(), exercise: None }
+    }
+
+    /// Chapter `A` with the tags `a1` and `a2`, then chapter `B` with `b1`.
+    fn code_book() -> CodeBook {
+        CodeBook { chapters: vec![
+            Chapter { name: String::from("A"), path: None, code_tags: vec![code_tag(0, "a1", 0), code_tag(0, "a2", 1)] },
+            Chapter { name: String::from("B"), path: None, code_tags: vec![code_tag(1, "b1", 0)] }
+        ] }
+    }
+
+    fn line<'a>(content: &str, start: &'a CodeTag, end: Option<&'a CodeTag>) -> SourceLine<'a> {
+        let location = Location { parent: None, kind: String::from("file"), name: None, is_function_declaration: false, signature: None };
+        SourceLine { content: String::from(content), location, start, end }
+    }
+
+    fn source_file<'a>(lines: Vec<SourceLine<'a>>) -> SourceFile<'a> {
+        SourceFile { path: PathBuf::from("lox/Lox.java"), lines }
+    }
+
+    fn diff(source_file: &SourceFile, from: Option<&CodeTag>, to: &CodeTag) -> String {
+        let mut result = String::new();
+        file_diff(&mut result, source_file, from, to);
+        result
+    }
+
+    #[test]
+    fn hunks_merge_overlapping_context() {
+        let changes: Vec<Change> = (0..20).map(|i| if [0, 5, 15].contains(&i) { Change::Add } else { Change::Keep }).collect();
+        let edits: Vec<Edit> = changes.iter().enumerate()
+            .map(|(i, &change)| Edit { change, content: "", old_line: i, new_line: i })
+            .collect();
+        assert_eq!(hunks(&edits), vec![(0, 9), (12, 19)]);
+    }
+
+    #[test]
+    fn new_file_is_numbered_from_empty_range() {
+        let book = code_book();
+        let a1 = &book.chapters[0].code_tags[0];
+        let file = source_file(vec![line("one", a1, None), line("two", a1, None)]);
+        assert_eq!(diff(&file, None, a1), "diff --git a/lox/Lox.java b/lox/Lox.java\n\
+            new file mode 100644\n--- /dev/null\n+++ b/lox/Lox.java\n\
+            @@ -0,0 +1,2 @@\n+one\n+two\n");
+    }
+
+    #[test]
+    fn deleted_file_is_numbered_to_empty_range() {
+        let book = code_book();
+        let (a1, b1) = (&book.chapters[0].code_tags[0], &book.chapters[1].code_tags[0]);
+        let file = source_file(vec![line("one", a1, Some(b1)), line("two", a1, Some(b1))]);
+        assert_eq!(diff(&file, Some(a1), b1), "diff --git a/lox/Lox.java b/lox/Lox.java\n\
+            deleted file mode 100644\n--- a/lox/Lox.java\n+++ /dev/null\n\
+            @@ -1,2 +0,0 @@\n-one\n-two\n");
+    }
+
+    #[test]
+    fn changed_lines_keep_context() {
+        let book = code_book();
+        let (a1, a2) = (&book.chapters[0].code_tags[0], &book.chapters[0].code_tags[1]);
+        let file = source_file(vec![
+            line("one", a1, None),
+            line("two", a1, Some(a2)),
+            line("deux", a2, None),
+            line("three", a1, None)
+        ]);
+        assert_eq!(diff(&file, Some(a1), a2), "diff --git a/lox/Lox.java b/lox/Lox.java\n\
+            --- a/lox/Lox.java\n+++ b/lox/Lox.java\n\
+            @@ -1,3 +1,3 @@\n one\n-two\n+deux\n three\n");
+        assert_eq!(diff(&file, Some(a2), a2), "");
+    }
+
+    #[test]
+    fn patch_series_has_one_step_per_chapter() {
+        let book = code_book();
+        let (a1, a2, b1) = (&book.chapters[0].code_tags[0], &book.chapters[0].code_tags[1], &book.chapters[1].code_tags[0]);
+        let files = vec![source_file(vec![line("one", a1, None), line("two", a2, None), line("three", b1, None)])];
+
+        let series = patch_series(&book, &files, None, b1);
+        let subjects: Vec<&str> = series.lines().filter(|l| l.starts_with("Subject: ")).collect();
+        assert_eq!(subjects, vec!["Subject: [PATCH 1/2] A/a2", "Subject: [PATCH 2/2] B/b1"]);
+
+        // Steps stop at `to`, and start after `from`.
+        let series = patch_series(&book, &files, None, a1);
+        assert!(series.contains("Subject: [PATCH 1/1] A/a1\n"));
+        let series = patch_series(&book, &files, Some(a1), b1);
+        assert!(series.contains("[PATCH 1/2] A/a2\n") && series.contains("[PATCH 2/2] B/b1\n"));
+        assert!(!series.contains("+one"));
+    }
+}
@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+
+use serde_json::{json, Value};
+
+use crate::{config::Configuration, preprocessor::{Caption, CaptionPart, CodeBook, Location, Snippet}};
+
+fn location_json(location: Option<&Location>) -> Value {
+    match location {
+        Some(location) => Value::Array(location.chain().iter().map(|l| json!({
+            "kind": l.kind,
+            "name": l.name,
+            "is_function_declaration": l.is_function_declaration,
+            "signature": l.signature
+        })).collect()),
+        None => Value::Null
+    }
+}
+
+/// A caption as an array of segments, each an array of `{"text": ...}` and
+/// `{"code": ...}` parts.
+fn caption_json(caption: Option<Caption>) -> Value {
+    match caption {
+        Some(caption) => Value::Array(caption.segments.iter().map(|segment| {
+            Value::Array(segment.iter().map(|part| match part {
+                CaptionPart::Text(text) => json!({ "text": text }),
+                CaptionPart::Code(code) => json!({ "code": code })
+            }).collect())
+        }).collect()),
+        None => Value::Null
+    }
+}
+
+/// Exports the resolved snippets in book order, each location being given as
+/// the chain of its enclosing locations, from the file down, along with the
+/// caption built from it.
+pub fn export_snippets(code_book: &CodeBook, snippets: &HashMap<&str, Vec<Snippet>>, config: &Configuration) -> Value {
+    let mut snippets: Vec<&Snippet> = snippets.values().flatten().collect();
+    snippets.sort_by(|a, b| (a.code_tag.chapter, a.code_tag.index, &a.file).cmp(&(b.code_tag.chapter, b.code_tag.index, &b.file)));
+    Value::Array(snippets.iter().map(|snippet| json!({
+        "chapter": code_book.chapters[snippet.code_tag.chapter].name,
+        "tag": snippet.code_tag.name,
+        "file": snippet.file,
+        "location": location_json(snippet.location.as_ref()),
+        "preceding_location": location_json(snippet.preceding_location.as_ref()),
+        "caption": caption_json(snippet.location_caption(&config.captions, config.overload_signatures)),
+        "first_line": snippet.first_line,
+        "last_line": snippet.last_line,
+        "context_before": snippet.context_before,
+        "removed": snippet.removed,
+        "added": snippet.added,
+        "context_after": snippet.context_after
+    })).collect())
+}
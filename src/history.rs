@@ -0,0 +1,69 @@
+use std::{fs, path::Path, process::Command};
+
+use mdbook::errors::Error;
+
+use crate::preprocessor::{CodeBook, CodeTag, SourceFile};
+
+const AUTHOR_NAME: &str = "mdbook-codetags";
+const AUTHOR_EMAIL: &str = "mdbook-codetags@localhost";
+
+fn git(repository: &Path, args: &[&str]) -> Result<(), Error> {
+    let status = Command::new("git")
+        .current_dir(repository)
+        .args(args)
+        .env("GIT_AUTHOR_NAME", AUTHOR_NAME)
+        .env("GIT_AUTHOR_EMAIL", AUTHOR_EMAIL)
+        .env("GIT_COMMITTER_NAME", AUTHOR_NAME)
+        .env("GIT_COMMITTER_EMAIL", AUTHOR_EMAIL)
+        .status()?;
+    if !status.success() {
+        return Err(Error::msg(format!("`git {}` failed with {}", args.join(" "), status)))
+    }
+    Ok(())
+}
+
+/// Writes every source file as it stands at `at`, removing the files which
+/// do not exist yet or anymore at that point.
+fn write_snapshot(repository: &Path, source_files: &[SourceFile], at: &CodeTag) -> Result<(), Error> {
+    for source_file in source_files {
+        let path = repository.join(&source_file.path);
+        let mut content = String::new();
+        for line in source_file.lines.iter().filter(|l| l.is_present_at(at)) {
+            content.push_str(&line.content);
+            content.push('\n');
+        }
+        if !content.is_empty() {
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(&path, content)?;
+        } else if path.exists() {
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Creates a git repository in `repository` holding one commit per chapter,
+/// or per code tag when `per_tag` is set, in book order.
+pub fn build_history(code_book: &CodeBook, source_files: &[SourceFile], repository: &Path, per_tag: bool) -> Result<(), Error> {
+    if repository.exists() && repository.read_dir()?.next().is_some() {
+        return Err(Error::msg(format!("{} already exists and is not empty", repository.display())))
+    }
+    fs::create_dir_all(repository)?;
+    git(repository, &["init", "--quiet"])?;
+
+    // The last chapter only holds the static tags.
+    let chapters = &code_book.chapters[..code_book.chapters.len() - 1];
+    let points: Vec<&CodeTag> = if per_tag {
+        chapters.iter().flat_map(|c| &c.code_tags).collect()
+    } else {
+        chapters.iter().filter_map(|c| c.code_tags.last()).collect()
+    };
+
+    for point in points {
+        write_snapshot(repository, source_files, point)?;
+        let message = format!("{}/{}", code_book.chapters[point.chapter].name, point.name);
+        git(repository, &["add", "--all"])?;
+        git(repository, &["commit", "--quiet", "--allow-empty", "--message", &message])?;
+    }
+    Ok(())
+}
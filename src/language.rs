@@ -0,0 +1,346 @@
+use std::{ffi::OsStr, path::Path};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::preprocessor::Location;
+
+/// What a kind of location stands for, which decides how captions name it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KindCategory {
+    File,
+    /// Classes, interfaces, structs, modules and the like.
+    Type,
+    /// Functions, methods and constructors.
+    Function,
+    Other
+}
+
+/// What the parser and the captions need to know of a programming language:
+/// how to track the location of the lines of its source files, and how to
+/// name the kinds of these locations.
+pub trait LanguageBackend: Sync {
+    /// The extensions of the source files of the language, without the dot.
+    fn extensions(&self) -> &[&str];
+
+    /// The location of `line`, when the line enters a new one from `location`.
+    fn location_before(&self, location: &Location, line: &str) -> Option<Location>;
+
+    /// The location following `line`, which was at `location`. `entered`
+    /// tells whether `location` comes from `location_before` on this line.
+    fn location_after(&self, location: &Location, line: &str, entered: bool) -> Location;
+
+    fn category(&self, kind: &str) -> KindCategory {
+        match kind {
+            "file" => KindCategory::File,
+            "class" | "interface" | "enum" | "record" | "annotation" | "struct" | "union" | "trait" | "impl" | "module" => KindCategory::Type,
+            "constructor" | "function" | "method" => KindCategory::Function,
+            _ => KindCategory::Other
+        }
+    }
+
+    /// The word naming the kind of location `kind` in captions, as in
+    /// `in interface Expr`.
+    fn kind_word<'a>(&self, kind: &'a str) -> &'a str {
+        kind
+    }
+
+    /// The parameter types of the parameter list `signature`, shortened to
+    /// tell overloaded functions apart in captions.
+    fn abbreviate_signature(&self, signature: &str) -> String {
+        String::from(signature)
+    }
+}
+
+/// Splits `text` on the commas which are not within angle brackets or parentheses.
+fn split_top_level(text: &str) -> Vec<&str> {
+    let mut result = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '<' | '(' => depth += 1,
+            '>' | ')' => depth -= 1,
+            ',' if depth == 0 => {
+                result.push(&text[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    result.push(&text[start..]);
+    result
+}
+
+/// The backend of the source file at `path`, found from its extension.
+pub fn backend_for(path: &Path) -> Option<&'static dyn LanguageBackend> {
+    let extension = path.extension().and_then(OsStr::to_str)?;
+    BACKENDS.iter().copied().find(|backend| backend.extensions().contains(&extension))
+}
+
+const BACKENDS: &[&dyn LanguageBackend] = &[&Java];
+
+lazy_static!{
+    static ref CONSTRUCTOR_PATTERN: Regex = Regex::new("^\\s+(?:(?:public|protected|private) )?([A-Z][a-z]\\w+)\\(([^)]*)").unwrap();
+    static ref FUNCTION_PATTERN: Regex = Regex::new("([\\w?]+)[>\\[\\]]*\\*? (\\w+)\\(([^)]*)").unwrap();
+    static ref VARIABLE_PATTERN: Regex = Regex::new("^\\w+\\*? (\\w+)(;| = )").unwrap();
+    static ref TYPE_PATTERN: Regex = Regex::new(
+        "^\\s*(?:(?:public|protected|private|abstract|static|final|sealed|non-sealed|strictfp) )*(class|enum|interface|record|@interface) ([A-Z]\\w*)"
+    ).unwrap();
+    // Annotations are capitalized, unlike the `@interface` keyword.
+    static ref ANNOTATIONS_PATTERN: Regex = Regex::new("^(\\s*)(?:@[A-Z][\\w.]*(?:\\([^)]*\\))? *)+").unwrap();
+
+    static ref KEYWORDS: Vec<&'static str> = vec!("new", "return", "throw", "yield", "else", "public", "protected", "private");
+
+    // pub static ref STRUCT_PATTERN: Regex = Regex::new("^struct (\\w+)? \\{$").unwrap();
+    // pub static ref NAMED_TYPEDEF_PATTERN: Regex = Regex::new("^typedef (enum|struct|union) (\\w+) \\{$").unwrap();
+    // pub static ref UNNAMED_TYPEDEF_PATTERN: Regex = Regex::new("^typedef (enum|struct|union) \\{$").unwrap();
+    // pub static ref TYPEDEF_NAME_PATTERN: Regex = Regex::new("^} (\\w+);$").unwrap();
+}
+
+/// Tracks locations in Java sources from the indentation of their lines,
+/// which is assumed to be two spaces per level.
+pub struct Java;
+
+impl Java {
+    /// The type of a parameter like `final Map<String, Expr> values`, without
+    /// its type arguments nor package or outer class, as in `Map`.
+    fn abbreviate_parameter(parameter: &str) -> String {
+        let words: Vec<&str> = parameter.split_whitespace()
+            .filter(|w| !w.starts_with('@') && *w != "final")
+            .collect();
+        // The last word is the name, unless the parameter was cut short.
+        let declared = match words.len() {
+            0 => return String::new(),
+            1 => words[0],
+            _ => &words[..words.len() - 1].join(" ")
+        };
+        let mut result = String::new();
+        let mut depth = 0;
+        for c in declared.chars() {
+            match c {
+                '<' => depth += 1,
+                '>' => depth -= 1,
+                c if depth == 0 && !c.is_whitespace() => result.push(c),
+                _ => {}
+            }
+        }
+        // Array and varargs suffixes go before the package is split off, as
+        // in `java.lang.String...`.
+        let mut declared = result.as_str();
+        let mut suffix = String::new();
+        while let Some((rest, marker)) = declared.strip_suffix("[]").map(|r| (r, "[]"))
+            .or_else(|| declared.strip_suffix("...").map(|r| (r, "..."))) {
+            suffix.insert_str(0, marker);
+            declared = rest;
+        }
+        let name = declared.rsplit('.').next().unwrap_or(declared);
+        format!("{}{}", name, suffix)
+    }
+}
+
+impl LanguageBackend for Java {
+    fn extensions(&self) -> &[&str] {
+        &["java"]
+    }
+
+    fn abbreviate_signature(&self, signature: &str) -> String {
+        let types: Vec<String> = split_top_level(signature).into_iter()
+            .filter(|p| !p.trim().is_empty())
+            .map(Java::abbreviate_parameter)
+            .collect();
+        types.join(", ")
+    }
+
+    fn location_before(&self, location: &Location, line: &str) -> Option<Location> {
+        // The parameter list of a function goes on from the previous line,
+        // which ended with a comma.
+        if let Some(signature) = location.signature.as_deref().filter(|s| s.ends_with(',')) {
+            let closed = line.contains(')');
+            return Some(Location {
+                signature: Some(format!("{} {}", signature, line.split(')').next().unwrap().trim())),
+                is_function_declaration: closed && line.trim_end().ends_with(';'),
+                ..location.clone()
+            })
+        }
+
+        let line = &*ANNOTATIONS_PATTERN.replace(line, "$1");
+
+        // Types go first, as records have parameters like functions.
+        if let Some(c) = TYPE_PATTERN.captures(line) {
+            // Hack. Don't get caught by comments or string literals.
+            if !line.contains("//") && !line.contains('"') {
+                let kind = match c.get(1).unwrap().as_str() {
+                    "@interface" => "annotation",
+                    kind => kind
+                };
+                return Some(Location {
+                    parent: Some(Box::from(location.clone())),
+                    kind: String::from(kind),
+                    name: Some(String::from(c.get(2).unwrap().as_str())),
+                    is_function_declaration: false,
+                    signature: None
+                })
+            }
+            return None
+        }
+
+        if let Some(c) = FUNCTION_PATTERN.captures(line)
+            && !KEYWORDS.contains(&c.get(1).unwrap().as_str())
+            // Hack. Don't get caught by comments, string literals or lambdas.
+            && !line.contains("//") && !line.contains('"') && !line.contains("->") {
+            return Some(Location {
+                parent: Some(Box::new(location.clone())),
+                kind: String::from("method"),
+                name: Some(String::from(c.get(2).unwrap().as_str())),
+                is_function_declaration: line.ends_with(";"),
+                signature: Some(String::from(c.get(3).unwrap().as_str().trim()))
+            })
+        }
+
+        if let Some(c) = CONSTRUCTOR_PATTERN.captures(line) {
+            return Some(Location {
+                parent: Some(Box::new(location.clone())),
+                kind: String::from("constructor"),
+                name: Some(String::from(c.get(1).unwrap().as_str())),
+                is_function_declaration: false,
+                signature: Some(String::from(c.get(2).unwrap().as_str().trim()))
+            })
+        }
+        if let Some(c) = VARIABLE_PATTERN.captures(line) {
+            return Some(Location {
+                parent: Some(Box::from(location.clone())),
+                kind: String::from("variable"),
+                name: Some(String::from(c.get(1).unwrap().as_str())),
+                is_function_declaration: false,
+                signature: None
+            })
+        }
+        None
+    }
+
+    fn location_after(&self, location: &Location, line: &str, entered: bool) -> Location {
+        // Use "startsWith" to include lines like "} [aside-marker]".
+        let mut location = if line.starts_with("}") {
+            location.pop_to_depth(0)
+        } else if line.starts_with("  }") {
+            location.pop_to_depth(1)
+        } else if line.starts_with("    }") {
+            location.pop_to_depth(2)
+        } else {
+            location.clone()
+        };
+
+        // If we reached a function declaration, not a definition, then it's done after its parameters.
+        if location.is_function_declaration {
+            location = leave(location);
+        }
+
+        // Module variables are only a single line.
+        if location.kind == "variable" {
+            location = leave(location);
+        }
+
+        // One-line types, as in `class ParseError extends RuntimeException {}`
+        // or `record Point(int x, int y) {}`.
+        if entered && self.category(&location.kind) == KindCategory::Type && line.trim_end().ends_with('}') {
+            location = leave(location);
+        }
+        location
+    }
+}
+
+/// The location enclosing `location`, if any.
+fn leave(location: Location) -> Location {
+    match location.parent {
+        Some(parent) => *parent,
+        None => location
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The location names of each line of a Java file, from the file down.
+    fn locations(lines: &[&str]) -> Vec<String> {
+        let mut location = Location {
+            parent: None,
+            kind: String::from("file"),
+            name: Some(String::from("Main.java")),
+            is_function_declaration: false,
+            signature: None
+        };
+        let mut result = Vec::new();
+        for line in lines {
+            let entered = match Java.location_before(&location, line) {
+                Some(l) => {
+                    location = l;
+                    true
+                }
+                None => false
+            };
+            let names: Vec<&str> = location.chain().iter().filter_map(|l| l.name.as_deref()).collect();
+            result.push(names.join("/"));
+            location = Java.location_after(&location, line, entered);
+        }
+        result
+    }
+
+    #[test]
+    fn constructors_of_nested_classes() {
+        assert_eq!(locations(&[
+            "class Main {",
+            "  public Main() {",
+            "  }",
+            "  static class Inner {",
+            "    public Inner(int x) {",
+            "      int y = x;",
+            "    }",
+            "  }",
+            "}"
+        ]), vec!["Main.java/Main", "Main.java/Main/Main", "Main.java/Main/Main", "Main.java/Main/Inner", "Main.java/Main/Inner/Inner",
+            "Main.java/Main/Inner/Inner", "Main.java/Main/Inner/Inner", "Main.java/Main/Inner", "Main.java/Main"]);
+    }
+
+    #[test]
+    fn one_line_types_end_on_their_line() {
+        assert_eq!(locations(&[
+            "enum Color { RED(\"red\") }",
+            "record Point(int x, int y) {}",
+            "class Main {",
+            "  enum Size { BIG(\"big\") }",
+            "  private static class ParseError extends RuntimeException {}",
+            "  void run() {",
+            "  }",
+            "}"
+        ]), vec!["Main.java", "Main.java/Point", "Main.java/Main", "Main.java/Main", "Main.java/Main/ParseError",
+            "Main.java/Main/run", "Main.java/Main/run", "Main.java/Main"]);
+    }
+
+    #[test]
+    fn abbreviates_generic_parameters() {
+        assert_eq!(Java.abbreviate_signature("Map<String, List<Expr>> values, T item"), "Map, T");
+        assert_eq!(Java.abbreviate_signature("Visitor<R> visitor"), "Visitor");
+    }
+
+    #[test]
+    fn abbreviates_arrays_and_varargs() {
+        assert_eq!(Java.abbreviate_signature("String[] args"), "String[]");
+        assert_eq!(Java.abbreviate_signature("int[][] grid, Object... rest"), "int[][], Object...");
+        assert_eq!(Java.abbreviate_signature("List<String>[] lists"), "List[]");
+    }
+
+    #[test]
+    fn abbreviates_qualified_names() {
+        assert_eq!(Java.abbreviate_signature("java.lang.String... args"), "String...");
+        assert_eq!(Java.abbreviate_signature("java.util.Map<String, Integer> map, Expr.Binary expr"), "Map, Binary");
+        assert_eq!(Java.abbreviate_signature("java.lang.String[] names"), "String[]");
+    }
+
+    #[test]
+    fn drops_annotations_and_modifiers() {
+        assert_eq!(Java.abbreviate_signature("@Nullable final Token name, final int line"), "Token, int");
+        assert_eq!(Java.abbreviate_signature(""), "");
+    }
+}
@@ -0,0 +1,38 @@
+//! An mdbook preprocessor replacing `^code tag` directives with the code that
+//! the tag adds to, and removes from, a tree of marked source files.
+//!
+//! The engine goes through the same steps as the preprocessor:
+//!
+//! 1. [`CodeTagsHighlighterPreprocessor::collect_valid_code_tags`] builds the
+//!    [`CodeBook`] of the directives of a book,
+//! 2. [`CodeTagsHighlighterPreprocessor::parse_valid_sources`] parses the
+//!    source tree against it,
+//! 3. [`CodeTagsHighlighterPreprocessor::build_snippets`] gathers the
+//!    [`Snippet`] of each code tag, which
+//!    [`CodeTagsHighlighterPreprocessor::find_snippet`] looks up and a
+//!    [`SnippetRenderer`] renders.
+//!
+//! [`CodeTagsHighlighterPreprocessor::render_book`] does all of it for a whole
+//! book, with any [`SnippetRenderer`].
+
+mod cache;
+pub mod captions;
+pub mod config;
+pub mod diff;
+pub mod export;
+pub mod history;
+pub mod language;
+pub mod list;
+pub mod options;
+pub mod preprocessor;
+pub mod render;
+
+pub use captions::Captions;
+pub use config::{Configuration, LatexPackage};
+pub use language::{Java, KindCategory, LanguageBackend};
+pub use options::{CodeTagOptions, FileOptions, LineRange};
+pub use preprocessor::{
+    Caption, CaptionPart, Chapter, CodeBook, CodeTag, CodeTagsHighlighterPreprocessor, LineKind, Location, MarkerError,
+    Snippet, SourceFile, SourceFileParser, SourceLine,
+};
+pub use render::{HtmlRenderer, LatexRenderer, MarkdownRenderer, SnippetRenderer};
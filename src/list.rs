@@ -0,0 +1,82 @@
+use std::{collections::HashMap, path::Path};
+
+use serde_json::{json, Value};
+
+use crate::preprocessor::{CodeBook, CodeTag, SourceFile};
+
+#[derive(Default)]
+struct TagStats<'a> {
+    files: Vec<&'a Path>,
+    added: usize,
+    removed: usize
+}
+
+impl<'a> TagStats<'a> {
+    fn touch(&mut self, path: &'a Path) {
+        if !self.files.contains(&path) {
+            self.files.push(path);
+        }
+    }
+}
+
+fn collect_stats<'a>(source_files: &'a [SourceFile]) -> HashMap<&'a CodeTag, TagStats<'a>> {
+    let mut result: HashMap<&CodeTag, TagStats> = HashMap::new();
+    for source_file in source_files {
+        for line in &source_file.lines {
+            let stats = result.entry(line.start).or_default();
+            stats.touch(&source_file.path);
+            stats.added += 1;
+            if let Some(end) = line.end {
+                let stats = result.entry(end).or_default();
+                stats.touch(&source_file.path);
+                stats.removed += 1;
+            }
+        }
+    }
+    result
+}
+
+/// Lists every code tag in book order, one per line.
+pub fn list_text(code_book: &CodeBook, source_files: &[SourceFile]) -> String {
+    let stats = collect_stats(source_files);
+    let empty = TagStats::default();
+    let mut result = String::new();
+    for chapter in &code_book.chapters {
+        for code_tag in &chapter.code_tags {
+            let stats = stats.get(code_tag).unwrap_or(&empty);
+            result.push_str(&format!("{}/{} [{}]", chapter.name, code_tag.name, code_tag.index));
+            let options = code_tag.options.describe();
+            if !options.is_empty() {
+                result.push_str(&format!(" ({})", options.join(", ")));
+            }
+            result.push_str(&format!(": +{} -{}", stats.added, stats.removed));
+            if !stats.files.is_empty() {
+                let files: Vec<_> = stats.files.iter().map(|f| f.display().to_string()).collect();
+                result.push_str(&format!(" in {}", files.join(", ")));
+            }
+            result.push('\n');
+        }
+    }
+    result
+}
+
+/// Lists every code tag in book order as a JSON array.
+pub fn list_json(code_book: &CodeBook, source_files: &[SourceFile]) -> Value {
+    let stats = collect_stats(source_files);
+    let empty = TagStats::default();
+    let tags = code_book.chapters.iter().flat_map(|chapter| {
+        chapter.code_tags.iter().map(|code_tag| {
+            let stats = stats.get(code_tag).unwrap_or(&empty);
+            json!({
+                "chapter": chapter.name,
+                "name": code_tag.name,
+                "index": code_tag.index,
+                "options": code_tag.options.describe(),
+                "files": stats.files,
+                "added": stats.added,
+                "removed": stats.removed
+            })
+        })
+    });
+    Value::Array(tags.collect())
+}
@@ -1,11 +1,27 @@
-use std::{io, process};
+use std::{io, path::PathBuf, process};
 
-use clap::{Arg, ArgMatches, Command};
-use mdbook::{errors::Error, preprocess::{CmdPreprocessor, Preprocessor}};
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use mdbook::{errors::Error, preprocess::{CmdPreprocessor, Preprocessor}, MDBook};
 use semver::{Version, VersionReq};
 
-mod preprocessor;
-mod config;
+use mdbook_codetags::{diff, export, history, list, CodeTagsHighlighterPreprocessor};
+
+fn book_arg() -> Arg {
+    Arg::new("book")
+        .long("book")
+        .value_name("PATH")
+        .value_parser(clap::value_parser!(PathBuf))
+        .default_value(".")
+        .help("Root directory of the book, or its book.toml")
+}
+
+fn format_arg() -> Arg {
+    Arg::new("format")
+        .long("format")
+        .value_parser(["text", "json"])
+        .default_value("text")
+        .help("Output format")
+}
 
 fn cmd() -> Command {
     Command::new("codetags")
@@ -17,22 +33,128 @@ fn cmd() -> Command {
                 .arg(Arg::new("renderer").required(true))
                 .about("Check whether a renderer is supported by this preprocessor"),
         )
+        .subcommand(
+            Command::new("diff")
+                .arg(book_arg())
+                .arg(Arg::new("from")
+                    .long("from")
+                    .value_name("POINT")
+                    .help("Starting point, defaults to the end of the previous chapter (or the beginning of the book with --series)"))
+                .arg(Arg::new("series")
+                    .long("series")
+                    .action(ArgAction::SetTrue)
+                    .help("Output one patch per chapter in mailbox format, for git am"))
+                .arg(Arg::new("to")
+                    .required(true)
+                    .value_name("POINT")
+                    .help("Ending point, as `Chapter` or `Chapter/tag`"))
+                .about("Print the unified diff of the sources between two points of the book"),
+        )
+        .subcommand(
+            Command::new("history")
+                .arg(book_arg())
+                .arg(Arg::new("per-tag")
+                    .long("per-tag")
+                    .action(ArgAction::SetTrue)
+                    .help("Create one commit per code tag instead of one per chapter"))
+                .arg(Arg::new("output")
+                    .required(true)
+                    .value_name("DIR")
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Directory of the git repository to create"))
+                .about("Build a git repository with one commit per chapter of the book"),
+        )
+        .subcommand(
+            Command::new("check")
+                .arg(book_arg())
+                .about("Validate the code markers of the sources without rendering the book"),
+        )
+        .subcommand(
+            Command::new("list")
+                .arg(book_arg())
+                .arg(format_arg())
+                .about("List the code tags of the book in order"),
+        )
+        .subcommand(
+            Command::new("export")
+                .arg(book_arg())
+                .about("Export the resolved snippets of the book as JSON"),
+        )
 }
 
 fn main() {
-    env_logger::init();
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
 
     let matches = cmd().get_matches();
-    let preproc = preprocessor::CodeTagsHighlighterPreprocessor;
+    let preproc = CodeTagsHighlighterPreprocessor;
 
     if let Some(sub_args) = matches.subcommand_matches("supports") {
         handle_supports(&preproc, sub_args);
-    } else if let Err(e) = handle_preprocessing(&preproc) {
+    }
+
+    let result = if let Some(sub_args) = matches.subcommand_matches("diff") {
+        handle_diff(&preproc, sub_args)
+    } else if let Some(sub_args) = matches.subcommand_matches("history") {
+        handle_history(&preproc, sub_args)
+    } else if let Some(sub_args) = matches.subcommand_matches("check") {
+        handle_check(&preproc, sub_args)
+    } else if let Some(sub_args) = matches.subcommand_matches("list") {
+        handle_list(&preproc, sub_args)
+    } else if let Some(sub_args) = matches.subcommand_matches("export") {
+        handle_export(&preproc, sub_args)
+    } else {
+        handle_preprocessing(&preproc)
+    };
+    if let Err(e) = result {
         log::error!("{}", e);
         process::exit(1);
     }
 }
 
+fn load_book(sub_args: &ArgMatches) -> Result<MDBook, Error> {
+    let path = sub_args.get_one::<PathBuf>("book").expect("Defaulted argument");
+    if path.is_file() {
+        MDBook::load(path.parent().unwrap_or(path))
+    } else {
+        MDBook::load(path)
+    }
+}
+
+fn handle_diff(pre: &CodeTagsHighlighterPreprocessor, sub_args: &ArgMatches) -> Result<(), Error> {
+    let md = load_book(sub_args)?;
+    let config = pre.configuration(&md.config);
+    let code_book = pre.collect_valid_code_tags(&md.book)?;
+
+    let find_point = |point: &str| code_book.find_point(point)
+        .ok_or_else(|| Error::msg(format!("unknown chapter or code tag `{}`", point)));
+    let to = find_point(sub_args.get_one::<String>("to").expect("Required argument"))?;
+    let series = sub_args.get_flag("series");
+    let from = match sub_args.get_one::<String>("from") {
+        Some(point) => Some(find_point(point)?),
+        // A patch series replays the book from its beginning by default.
+        None if series => None,
+        None => code_book.previous_point(to),
+    };
+
+    let source_files = pre.parse_valid_sources(&code_book, &md.root, &config)?;
+    if series {
+        print!("{}", diff::patch_series(&code_book, &source_files, from, to));
+    } else {
+        print!("{}", diff::unified_diff(&source_files, from, to));
+    }
+    Ok(())
+}
+
+fn handle_history(pre: &CodeTagsHighlighterPreprocessor, sub_args: &ArgMatches) -> Result<(), Error> {
+    let md = load_book(sub_args)?;
+    let config = pre.configuration(&md.config);
+    let code_book = pre.collect_valid_code_tags(&md.book)?;
+    let source_files = pre.parse_valid_sources(&code_book, &md.root, &config)?;
+
+    let output = sub_args.get_one::<PathBuf>("output").expect("Required argument");
+    history::build_history(&code_book, &source_files, output, sub_args.get_flag("per-tag"))
+}
+
 fn handle_preprocessing(pre: &dyn Preprocessor) -> Result<(), Error> {
 
     // <debug>
@@ -76,4 +198,44 @@ fn handle_supports(pre: &dyn Preprocessor, sub_args: &ArgMatches) -> ! {
     } else {
         process::exit(1);
     }
-}
\ No newline at end of file
+}
+fn handle_check(pre: &CodeTagsHighlighterPreprocessor, sub_args: &ArgMatches) -> Result<(), Error> {
+    let md = load_book(sub_args)?;
+    let config = pre.configuration(&md.config);
+    let problems = pre.check(&md.book, &md.root, &config)?;
+
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    if !problems.is_empty() {
+        return Err(Error::msg(format!("found {} problems", problems.len())))
+    }
+    Ok(())
+}
+
+fn handle_list(pre: &CodeTagsHighlighterPreprocessor, sub_args: &ArgMatches) -> Result<(), Error> {
+    let md = load_book(sub_args)?;
+    let config = pre.configuration(&md.config);
+    let code_book = pre.collect_valid_code_tags(&md.book)?;
+    let source_files = pre.parse_valid_sources(&code_book, &md.root, &config)?;
+
+    if sub_args.get_one::<String>("format").is_some_and(|f| f == "json") {
+        serde_json::to_writer_pretty(io::stdout(), &list::list_json(&code_book, &source_files))?;
+        println!();
+    } else {
+        print!("{}", list::list_text(&code_book, &source_files));
+    }
+    Ok(())
+}
+
+fn handle_export(pre: &CodeTagsHighlighterPreprocessor, sub_args: &ArgMatches) -> Result<(), Error> {
+    let md = load_book(sub_args)?;
+    let config = pre.configuration(&md.config);
+    let code_book = pre.collect_valid_code_tags(&md.book)?;
+    let source_files = pre.parse_valid_sources(&code_book, &md.root, &config)?;
+    let snippets = pre.build_snippets(&source_files);
+
+    serde_json::to_writer_pretty(io::stdout(), &export::export_snippets(&code_book, &snippets, &config))?;
+    println!();
+    Ok(())
+}
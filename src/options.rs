@@ -0,0 +1,253 @@
+use std::fmt;
+
+/// A range of lines, numbered from 1, as in `3-5` or `4`.
+#[derive(Debug, Hash, Eq, PartialEq, Clone, Copy)]
+pub struct LineRange {
+    pub first: usize,
+    pub last: usize
+}
+
+impl LineRange {
+    pub fn contains(&self, line: usize) -> bool {
+        self.first <= line && line <= self.last
+    }
+}
+
+impl fmt::Display for LineRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.first == self.last {
+            write!(f, "{}", self.first)
+        } else {
+            write!(f, "{}-{}", self.first, self.last)
+        }
+    }
+}
+
+/// The options of a `^code` directive, as in `^code tag (2 before, title = "Main")`.
+#[derive(Debug, Default, Hash, Eq, PartialEq, Clone)]
+pub struct CodeTagOptions {
+    /// Hide the location caption, `None` to follow the book-wide setting.
+    pub no_location: Option<bool>,
+    pub before_count: u32,
+    pub after_count: u32,
+    pub dedent: bool,
+    /// Render the snippet folded, inside a `<details>` element.
+    pub collapsed: bool,
+    /// Render the snippet as the hidden solution of an exercise.
+    pub solution: bool,
+    /// Render the code as is, without the `+`/`-` markers nor the removed lines.
+    pub no_diff: bool,
+    /// Source file to take the snippet from, when the tag spans several files.
+    pub file: Option<String>,
+    /// Added lines to show, the others are left out.
+    pub lines: Option<LineRange>,
+    pub title: Option<String>,
+    pub language: Option<String>,
+    /// Added lines to emphasize.
+    pub highlight: Vec<LineRange>
+}
+
+fn parse_count(value: &str, option: &str) -> Result<u32, String> {
+    value.trim().parse().map_err(|_| format!("invalid line count `{}` in option `{}`", value.trim(), option))
+}
+
+fn parse_range(value: &str, option: &str) -> Result<LineRange, String> {
+    let invalid = || format!("invalid line range `{}` in option `{}`", value, option);
+    let (first, last) = value.split_once('-').unwrap_or((value, value));
+    let first: usize = first.trim().parse().map_err(|_| invalid())?;
+    let last: usize = last.trim().parse().map_err(|_| invalid())?;
+    if first == 0 || last < first {
+        return Err(invalid())
+    }
+    Ok(LineRange { first, last })
+}
+
+/// Splits options on the commas which are not within double quotes.
+fn split_options(options: &str) -> Vec<&str> {
+    let mut result = Vec::new();
+    let mut in_quotes = false;
+    let mut start = 0;
+    for (i, c) in options.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => {
+                result.push(&options[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    result.push(&options[start..]);
+    result
+}
+
+fn unquote(value: &str) -> &str {
+    value.strip_prefix('"').and_then(|v| v.strip_suffix('"')).unwrap_or(value)
+}
+
+impl CodeTagOptions {
+    /// Parses the comma separated options of a directive. Options are either
+    /// flags, line counts like `2 before`, or `key = value` pairs whose value
+    /// may be double quoted to hold commas.
+    pub fn parse(options: &str) -> Result<CodeTagOptions, String> {
+        let mut result = CodeTagOptions::default();
+        if options.trim().is_empty() {
+            return Ok(result)
+        }
+        for option in split_options(options).into_iter().map(|o| o.trim()) {
+            if let Some((key, value)) = option.split_once('=') {
+                let value = unquote(value.trim());
+                match key.trim() {
+                    "file" => result.file = Some(String::from(value)),
+                    "lines" => result.lines = Some(parse_range(value, option)?),
+                    "title" => result.title = Some(String::from(value)),
+                    "language" => result.language = Some(String::from(value)),
+                    "highlight" => result.highlight = value.split([',', ' '])
+                        .filter(|r| !r.trim().is_empty())
+                        .map(|r| parse_range(r.trim(), option))
+                        .collect::<Result<_, _>>()?,
+                    key => return Err(format!("unknown option `{}`", key))
+                }
+            } else if let Some(count) = option.strip_suffix(" before") {
+                result.before_count = parse_count(count, option)?;
+            } else if let Some(count) = option.strip_suffix(" after") {
+                result.after_count = parse_count(count, option)?;
+            } else {
+                match option {
+                    "no location" => result.no_location = Some(true),
+                    "location" => result.no_location = Some(false),
+                    "dedent" => result.dedent = true,
+                    "collapsed" => result.collapsed = true,
+                    "solution" => result.solution = true,
+                    "no-diff" => result.no_diff = true,
+                    "" => return Err(String::from("empty option")),
+                    option => return Err(format!("unknown option `{}`", option))
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// The options as they would be written in a directive.
+    pub fn describe(&self) -> Vec<String> {
+        let mut result = Vec::new();
+        match self.no_location {
+            Some(true) => result.push(String::from("no location")),
+            Some(false) => result.push(String::from("location")),
+            None => {}
+        }
+        if self.before_count > 0 {
+            result.push(format!("{} before", self.before_count));
+        }
+        if self.after_count > 0 {
+            result.push(format!("{} after", self.after_count));
+        }
+        for (flag, name) in [(self.dedent, "dedent"), (self.collapsed, "collapsed"), (self.solution, "solution"), (self.no_diff, "no-diff")] {
+            if flag {
+                result.push(String::from(name));
+            }
+        }
+        for (key, value) in [("file", &self.file), ("title", &self.title), ("language", &self.language)] {
+            if let Some(value) = value {
+                result.push(format!("{} = \"{}\"", key, value));
+            }
+        }
+        if let Some(lines) = self.lines {
+            result.push(format!("lines = {}", lines));
+        }
+        if !self.highlight.is_empty() {
+            let ranges: Vec<String> = self.highlight.iter().map(|r| r.to_string()).collect();
+            result.push(format!("highlight = \"{}\"", ranges.join(", ")));
+        }
+        result
+    }
+}
+
+/// The options of a `^file` directive, as in `^file lox/Lox.java (at "Scanning/main")`.
+#[derive(Debug, Default)]
+pub struct FileOptions {
+    /// Point of the book to show the file at, as `Chapter/tag` or `Chapter`.
+    pub at: Option<String>,
+    /// Emphasize the lines added by the chapter of the point.
+    pub highlight_chapter: bool,
+    pub title: Option<String>,
+    pub language: Option<String>
+}
+
+impl FileOptions {
+    pub fn parse(options: &str) -> Result<FileOptions, String> {
+        let mut result = FileOptions::default();
+        if options.trim().is_empty() {
+            return Ok(result)
+        }
+        for option in split_options(options).into_iter().map(|o| o.trim()) {
+            // The point may also be written without `=`, as in `at "Chapter/tag"`.
+            if let Some(value) = option.strip_prefix("at ").map(str::trim).filter(|v| v.starts_with('"')) {
+                result.at = Some(String::from(unquote(value)));
+            } else if let Some((key, value)) = option.split_once('=') {
+                let value = unquote(value.trim());
+                match key.trim() {
+                    "at" => result.at = Some(String::from(value)),
+                    "title" => result.title = Some(String::from(value)),
+                    "language" => result.language = Some(String::from(value)),
+                    key => return Err(format!("unknown option `{}`", key))
+                }
+            } else {
+                match option {
+                    "highlight-chapter" => result.highlight_chapter = true,
+                    "" => return Err(String::from("empty option")),
+                    option => return Err(format!("unknown option `{}`", option))
+                }
+            }
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_counts_and_flags() {
+        let options = CodeTagOptions::parse("2 before, 1 after, no location, dedent, solution").unwrap();
+        assert_eq!((options.before_count, options.after_count), (2, 1));
+        assert_eq!(options.no_location, Some(true));
+        assert!(options.dedent && options.solution && !options.collapsed);
+        assert_eq!(CodeTagOptions::parse("location").unwrap().no_location, Some(false));
+        assert_eq!(CodeTagOptions::parse("two before"), Err(String::from("invalid line count `two` in option `two before`")));
+    }
+
+    #[test]
+    fn parses_ranges() {
+        let options = CodeTagOptions::parse("lines = 3-5, highlight = \"1, 4-6\"").unwrap();
+        assert_eq!(options.lines, Some(LineRange { first: 3, last: 5 }));
+        assert_eq!(options.highlight, vec![LineRange { first: 1, last: 1 }, LineRange { first: 4, last: 6 }]);
+        assert_eq!(CodeTagOptions::parse("lines = 7").unwrap().lines, Some(LineRange { first: 7, last: 7 }));
+        assert!(CodeTagOptions::parse("lines = 5-3").is_err());
+        assert!(CodeTagOptions::parse("lines = 0").is_err());
+    }
+
+    #[test]
+    fn keeps_commas_within_quotes() {
+        let options = CodeTagOptions::parse("title = \"Main, again (entry)\", 1 before").unwrap();
+        assert_eq!(options.title.as_deref(), Some("Main, again (entry)"));
+        assert_eq!(options.before_count, 1);
+    }
+
+    #[test]
+    fn rejects_unknown_and_empty_options() {
+        assert_eq!(CodeTagOptions::parse("folded"), Err(String::from("unknown option `folded`")));
+        assert_eq!(CodeTagOptions::parse("colour = red"), Err(String::from("unknown option `colour`")));
+        assert_eq!(CodeTagOptions::parse("dedent,, 1 before"), Err(String::from("empty option")));
+        assert_eq!(CodeTagOptions::parse("  "), Ok(CodeTagOptions::default()));
+    }
+
+    #[test]
+    fn file_point_may_omit_equals_sign() {
+        for options in ["at \"Scanning/main\"", "at = \"Scanning/main\"", "highlight-chapter, at \"Scanning/main\""] {
+            assert_eq!(FileOptions::parse(options).unwrap().at.as_deref(), Some("Scanning/main"));
+        }
+        assert_eq!(FileOptions::parse("at Scanning").unwrap_err(), "unknown option `at Scanning`");
+    }
+}
@@ -1,108 +1,206 @@
-use std::{collections::HashMap, ffi::OsStr, fs::File, io::{BufRead, BufReader, Error}, ops::Not, path::Path};
+use std::{borrow::Cow, collections::{HashMap, HashSet}, fmt, fs::File, io::{BufRead, BufReader}, ops::Not, path::{Path, PathBuf}, sync::atomic::{AtomicUsize, Ordering}, thread};
 
-use mdbook::{preprocess::Preprocessor, BookItem};
+use mdbook::{errors::Error, preprocess::Preprocessor, BookItem};
 use regex::Regex;
 use walkdir::WalkDir;
 use lazy_static::lazy_static;
 
-use crate::config::Configuration;
+use crate::{cache::{self, SourceCache}, captions::Captions, config::Configuration, language::{self, Java, KindCategory, LanguageBackend}, options::{CodeTagOptions, FileOptions}, render::{renderer_for, HtmlRenderer, SnippetRenderer}};
 
-struct CodeBook {
-    chapters: Vec<Chapter>
+pub struct CodeBook {
+    pub chapters: Vec<Chapter>
 }
 
 impl CodeBook {
     fn find_chapter(&self, name: &str) -> Option<&Chapter> {
-        return self.chapters.iter().find(|c|c.name == name);
+        self.chapters.iter().find(|c|c.name == name)
     }
 
-    fn find_code_tag<'a>(&'a self, chapter: &str, name: &str) -> Option<&'a CodeTag> {
+    pub fn find_code_tag<'a>(&'a self, chapter: &str, name: &str) -> Option<&'a CodeTag> {
         self.find_chapter(chapter).and_then(|chapter|{
             // special case to override omit 
             self.chapters.last().unwrap().find_code_tag(name).or_else(||chapter.find_code_tag(name))
         })
     }
+
+    /// Resolves a point in the book written either as `Chapter/tag` or as a
+    /// bare `Chapter`, which stands for the last code tag of that chapter.
+    pub fn find_point(&self, point: &str) -> Option<&CodeTag> {
+        if let Some((chapter, name)) = point.rsplit_once('/')
+            && let Some(code_tag) = self.find_chapter(chapter.trim()).and_then(|c|c.find_code_tag(name.trim())) {
+            return Some(code_tag)
+        }
+        self.find_chapter(point.trim()).and_then(|c|c.code_tags.last())
+    }
+
+    /// The point reached at the end of the chapter preceding the one of `code_tag`.
+    pub fn previous_point(&self, code_tag: &CodeTag) -> Option<&CodeTag> {
+        self.chapters[..code_tag.chapter].iter().rev().find_map(|c|c.code_tags.last())
+    }
+
+    /// The HTML id of the snippet of `code_tag`, derived from its chapter and
+    /// name so that links to it survive rebuilds.
+    pub fn anchor(&self, code_tag: &CodeTag) -> String {
+        format!("{}-{}", slugify(&self.chapters[code_tag.shown_in()].name), slugify(&code_tag.name))
+    }
 }
 
-struct Chapter {
-    name: String,
-    code_tags: Vec<CodeTag>
+fn slugify(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            result.push(c);
+        } else if !result.is_empty() && !result.ends_with('-') {
+            result.push('-');
+        }
+    }
+    result.truncate(result.trim_end_matches('-').len());
+    result
+}
+
+pub struct Chapter {
+    pub name: String,
+    /// Path of the chapter file relative to the book sources, `None` for the
+    /// static pseudo chapter.
+    pub path: Option<PathBuf>,
+    pub code_tags: Vec<CodeTag>
 }
 
 
 impl Chapter {
     fn find_code_tag(&self, name: &str) -> Option<&CodeTag> {
-        return self.code_tags.iter().find(|c|c.name == name);
+        self.code_tags.iter().find(|c|c.name == name)
     }
 }
 
 #[derive(Debug, Hash, Eq, PartialEq, Clone)]
-struct CodeTag {
-    chapter: usize,
-    name: String,
-    index: u32,
-    no_location: bool,
-    before_count: u32,
-    after_count: u32
+pub struct CodeTag {
+    pub chapter: usize,
+    pub name: String,
+    pub index: u32,
+    pub options: CodeTagOptions,
+    /// For a solution, the chapter of its exercise, as of the end of which it is shown.
+    pub exercise: Option<usize>
 }
 
 impl CodeTag {
-    fn is_before(&self, other: &CodeTag) -> bool {
+    /// The chapter showing the snippet of the tag.
+    pub fn shown_in(&self) -> usize {
+        self.exercise.unwrap_or(self.chapter)
+    }
+
+    pub fn is_before(&self, other: &CodeTag) -> bool {
         if self.chapter != other.chapter {
             return self.chapter < other.chapter
         }
-        return self.index < other.index
+        self.index < other.index
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Location {
+    pub parent: Option<Box<Location>>,
+    pub kind: String,
+    pub name: Option<String>,
+    pub is_function_declaration: bool,
+    /// The parameter list of a function, as written in the source.
+    pub signature: Option<String>
+}
+
+/// A piece of a location caption, either plain words or the name of some code.
 #[derive(Debug, Clone, PartialEq, Eq)]
-struct Location {
-    parent: Option<Box<Location>>,
-    kind: String,
-    name: Option<String>,
-    is_function_declaration: bool
+pub enum CaptionPart {
+    Text(String),
+    Code(String)
+}
+
+/// The caption telling where a snippet goes, as segments like `in class Lox`
+/// which read as a comma separated list. Renderers decide how to show names.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Caption {
+    pub segments: Vec<Vec<CaptionPart>>
+}
+
+impl Caption {
+    /// Formats the caption, passing the plain words through `text` and the
+    /// names through `code`.
+    pub fn format(&self, text: impl Fn(&str) -> String, code: impl Fn(&str) -> String) -> String {
+        let segments: Vec<String> = self.segments.iter().map(|segment| {
+            segment.iter().map(|part| match part {
+                CaptionPart::Text(t) => text(t),
+                CaptionPart::Code(c) => code(c)
+            }).collect()
+        }).collect();
+        segments.join(&text(", "))
+    }
 }
 
 impl Location {
-    fn to_html(&self, preceding: Option<&Location>, has_removed: bool) -> Vec<String> {
-        let mut result = Vec::new();
-        self.recurse(&mut result, preceding, has_removed);
-        return result;
+    /// The caption of a snippet at this location, following code at
+    /// `preceding`. The functions in `overloaded` are named along with the
+    /// types of their parameters.
+    pub fn caption(&self, preceding: Option<&Location>, has_removed: bool, captions: &Captions, language: &dyn LanguageBackend, overloaded: &[Location]) -> Caption {
+        let mut result = Caption::default();
+        self.recurse(&mut result, preceding, has_removed, captions, language, overloaded);
+        result
     }
 
-    fn recurse(&self, result: &mut Vec<String>, preceding: Option<&Location>, has_removed: bool) {
+    fn recurse(&self, result: &mut Caption, preceding: Option<&Location>, has_removed: bool, captions: &Captions, language: &dyn LanguageBackend, overloaded: &[Location]) {
         if let Some(parent) = &self.parent {
-            parent.recurse(result, preceding, has_removed);
+            parent.recurse(result, preceding, has_removed, captions, language, overloaded);
         }
-        if self.kind == "file" {
-            result.push(format!("<em>{}</em>", self.name.as_ref().unwrap().clone()));
+        let fill = |key: &str, location: &Location| {
+            let kind = captions.kind(language, &location.kind);
+            let params = match &location.signature {
+                Some(signature) if overloaded.contains(location) => language.abbreviate_signature(signature),
+                _ => String::new()
+            };
+            captions.fill(key, &kind, location.name.as_deref().unwrap_or(""), &params)
+        };
+        let is_function = |location: &Location| language.category(&location.kind) == KindCategory::Function;
+        let segment = if self.kind == "file" {
+            vec![CaptionPart::Code(String::from(self.name.as_ref().unwrap()))]
         } else if self.kind == "new" {
-            result.push(String::from("create new file"));
+            fill("new-file", self)
         } else if self.kind == "top" {
-            result.push(String::from("add to top of file"));
-        } else if self.kind == "class" { // TODO should more generic to all types
-            result.push(String::from(format!("in class <em>{}</em>", self.name.as_ref().unwrap().clone())));
-        } else if self.is_function() && preceding.map_or(false, |p| p == self) {
-            result.push(format!("in <em>{}</em>()", self.name.as_ref().unwrap()));
-        } else if self.is_function() && has_removed {
-            result.push(format!("{} <em>{}</em>()", self.kind, self.name.as_ref().unwrap()));
-        } else if self.parent.as_ref().map(|p| p.as_ref()) == preceding && !preceding.map_or(false, |p|p.is_file()) {
-            result.push(format!("in {} <em>{}</em>", preceding.unwrap().kind, preceding.unwrap().name.as_ref().unwrap()));
-        } else if preceding.map_or(false, |p|p == self) && !self.is_file() {
-            result.push(format!("in {} <em>$name</em>", self.kind));
-        } else if preceding.map_or(false, |p| p.is_function()) {
-            result.push(format!("add after <em>{}</em>()", preceding.unwrap().name.as_ref().unwrap()));
-        } else if !preceding.map_or(true, |p| p.is_file()) {
-            result.push(format!("add after {} <em>{}</em>", preceding.unwrap().kind, preceding.unwrap().name.as_ref().unwrap()));
+            fill("top-of-file", self)
+        } else if language.category(&self.kind) == KindCategory::Type {
+            fill("in-kind", self)
+        } else if is_function(self) && preceding.is_some_and(|p| p == self) {
+            fill("in-function", self)
+        } else if is_function(self) && has_removed {
+            fill("changed-function", self)
+        } else if let Some(preceding) = preceding.filter(|p| self.parent.as_deref() == Some(*p) && !p.is_file()) {
+            fill("in-kind", preceding)
+        } else if preceding.is_some_and(|p|p == self) && !self.is_file() {
+            fill("in-kind", self)
+        } else if let Some(preceding) = preceding.filter(|p| is_function(p)) {
+            fill("after-function", preceding)
+        } else if let Some(preceding) = preceding.filter(|p| !p.is_file()) {
+            fill("after-kind", preceding)
+        } else {
+            return
+        };
+        // The enclosing types are already named, code following one of them
+        // would repeat it.
+        if result.segments.last() != Some(&segment) {
+            result.segments.push(segment);
         }
     }
 
     fn is_file(&self) -> bool {
-        return self.kind == "file";
+        self.kind == "file"
     }
 
-    fn is_function(&self) -> bool {
-        return self.kind == "constructor" || self.kind == "function" || self.kind == "method";
+    /// The enclosing locations, from the file down to this one.
+    pub fn chain(&self) -> Vec<&Location> {
+        let mut result = Vec::new();
+        let mut current = Some(self);
+        while let Some(c) = current {
+            result.insert(0, c);
+            current = c.parent.as_deref();
+        }
+        result
     }
 
     fn depth(&self) -> usize {
@@ -110,25 +208,17 @@ impl Location {
         let mut result = 0;
         while let Some(c) = current  {
             result += 1;
-            current = if let Some(x) = &c.parent {
-                Some(&*x)
-            } else {
-                None
-            }
+            current = c.parent.as_deref();
         }
-        return result;
+        result
     }
     
-    fn pop_to_depth(&self, depth: usize) -> Location {
+    pub(crate) fn pop_to_depth(&self, depth: usize) -> Location {
         let mut locations: Vec<&Location> = Vec::new();
         let mut current = Some(self);
         while let Some(c) = current {
             locations.push(c);
-            current = if let Some(x) = &c.parent {
-                Some(&*x)
-            } else {
-                None
-            }
+            current = c.parent.as_deref();
         }
 
         // If we are already shallower, there is nothing to pop.
@@ -136,26 +226,45 @@ impl Location {
             return self.clone();
         }
 
-        return locations[locations.len() - depth - 1].clone();
+        locations[locations.len() - depth - 1].clone()
     }
 }
 
-struct Snippet {
-    code_tag: CodeTag,
-    location: Option<Location>,
-    preceding_location: Option<Location>,
-    first_line: usize,
-    last_line: usize,
-    context_before: Vec<String>,
-    context_after: Vec<String>,
-    added: Vec<String>,
-    removed: Vec<String>
+pub struct Snippet {
+    pub code_tag: CodeTag,
+    /// Path of the source file the snippet comes from, relative to the source root.
+    pub file: PathBuf,
+    pub location: Option<Location>,
+    pub preceding_location: Option<Location>,
+    pub first_line: usize,
+    pub last_line: usize,
+    pub context_before: Vec<String>,
+    pub context_after: Vec<String>,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// The functions around the snippet whose name is shared by another
+    /// function of the same scope at this point of the book.
+    pub overloaded: Vec<Location>
+}
+
+/// A function of a source file, as its scope and name.
+type FunctionKey<'a> = (Option<&'a Location>, Option<&'a str>);
+
+/// The role of a line in a rendered snippet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineKind {
+    Context,
+    Removed,
+    Added,
+    /// An added line emphasized by the `highlight` option.
+    Highlighted
 }
 
 impl Snippet {
-    fn new<'b>(code_tag: &CodeTag) -> Self {
-        return Snippet {
+    fn new(code_tag: &CodeTag, file: &Path) -> Self {
+        Snippet {
             code_tag: code_tag.clone(),
+            file: file.to_path_buf(),
             location: None,
             preceding_location: None,
             first_line: 0,
@@ -164,6 +273,7 @@ impl Snippet {
             context_after: Vec::new(),
             added: Vec::new(),
             removed: Vec::new(),
+            overloaded: Vec::new(),
         }
     }
 
@@ -181,25 +291,124 @@ impl Snippet {
         self.last_line = line_index;
     }
 
+    /// The indentation shared by all the non blank lines of the snippet.
+    fn common_indentation(&self) -> usize {
+        self.context_before.iter()
+            .chain(&self.removed)
+            .chain(&self.added)
+            .chain(&self.context_after)
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.chars().take_while(|c| c.is_whitespace()).count())
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// The number of columns removed from the lines by the `dedent` option.
+    pub fn dedent_width(&self) -> usize {
+        if self.code_tag.options.dedent { self.common_indentation() } else { 0 }
+    }
+
+    /// The lines to show, once the `dedent`, `no-diff`, `lines` and
+    /// `highlight` options are applied.
+    pub fn display_lines(&self) -> Vec<(LineKind, &str)> {
+        let options = &self.code_tag.options;
+        let dedent = self.dedent_width();
+        let mut result = Vec::new();
+        for line in &self.context_before {
+            result.push((LineKind::Context, strip_indentation(line, dedent)));
+        }
+        if !options.no_diff {
+            for line in &self.removed {
+                result.push((LineKind::Removed, strip_indentation(line, dedent)));
+            }
+        }
+        for (index, line) in self.added.iter().enumerate() {
+            let line_number = index + 1;
+            if options.lines.is_some_and(|r| !r.contains(line_number)) {
+                continue
+            }
+            let kind = if options.highlight.iter().any(|r| r.contains(line_number)) { LineKind::Highlighted } else { LineKind::Added };
+            result.push((kind, strip_indentation(line, dedent)));
+        }
+        for line in &self.context_after {
+            result.push((LineKind::Context, strip_indentation(line, dedent)));
+        }
+        result
+    }
+
+    /// The caption telling where the code of the snippet goes, `None` when
+    /// the snippet has no location.
+    /// The parameter types of overloaded functions are shown when
+    /// `signatures` is set.
+    pub fn location_caption(&self, captions: &Captions, signatures: bool) -> Option<Caption> {
+        let location = self.location.as_ref()?;
+        let language = language::backend_for(&self.file).unwrap_or(&Java);
+        let overloaded = if signatures { self.overloaded.as_slice() } else { &[] };
+        Some(location.caption(self.preceding_location.as_ref(), !self.removed.is_empty(), captions, language, overloaded))
+    }
+
+    /// The location caption to show, `None` when there is none or the
+    /// options hide it.
+    pub fn caption(&self, config: &Configuration) -> Option<Caption> {
+        if self.code_tag.options.no_location.unwrap_or(config.no_location) {
+            return None
+        }
+        self.location_caption(&config.captions, config.overload_signatures)
+    }
+
+    /// Whether `line` is in the code around the snippet. The code of the other
+    /// solutions, which sit in the static chapter as well, is left out.
+    fn is_context(&self, line: &SourceLine) -> bool {
+        match self.code_tag.exercise {
+            // Solutions show the code as of the end of their exercise chapter.
+            Some(chapter) => line.start == &self.code_tag || (line.is_present_after(chapter) && line.end != Some(&self.code_tag)),
+            None => line.is_present_at(&self.code_tag) && !(line.start.options.solution && line.start != &self.code_tag)
+        }
+    }
+
+    /// Finds the functions around the snippet which are overloaded, that is
+    /// have several signatures among the lines present at its code tag.
+    fn compute_overloads(&mut self, file: &SourceFile) {
+        let language = language::backend_for(&self.file).unwrap_or(&Java);
+        let is_function = |location: &Location| language.category(&location.kind) == KindCategory::Function;
+        // The lines of a parameter list spanning several lines only have the start of the signature.
+        let is_partial = |location: &Location| location.signature.as_deref().is_some_and(|s| s.ends_with(','));
+        let mut signatures: HashMap<FunctionKey, HashSet<Option<&str>>> = HashMap::new();
+        for line in file.lines.iter().filter(|l| self.is_context(l)) {
+            for location in line.location.chain().into_iter().filter(|l| is_function(l) && !is_partial(l)) {
+                signatures.entry((location.parent.as_deref(), location.name.as_deref()))
+                    .or_default()
+                    .insert(location.signature.as_deref());
+            }
+        }
+        let around = self.location.iter().chain(&self.preceding_location).flat_map(|l| l.chain());
+        for location in around.filter(|l| is_function(l)) {
+            let key = (location.parent.as_deref(), location.name.as_deref());
+            if signatures.get(&key).is_some_and(|s| s.len() > 1) && !self.overloaded.contains(location) {
+                self.overloaded.push(location.clone());
+            }
+        }
+    }
+
     fn compute_context(&mut self, file: &SourceFile) {
         for ii in 0 .. self.first_line {
             let i = self.first_line - 1 - ii;
-            if self.context_before.len() >= self.code_tag.before_count as usize {
+            if self.context_before.len() >= self.code_tag.options.before_count as usize {
                 break
             }
             let line = &file.lines[i];
-            if !line.is_present_at(&self.code_tag) {
+            if !self.is_context(line) {
                 continue
             } 
             self.context_before.insert(0, line.content.clone());
         }
 
         for i in self.last_line + 1..file.lines.len() {
-            if self.context_after.len() >= self.code_tag.after_count as usize {
+            if self.context_after.len() >= self.code_tag.options.after_count as usize {
                 break
             }
             let line = &file.lines[i];
-            if line.is_present_at(&self.code_tag) {
+            if self.is_context(line) {
                 self.context_after.push(line.content.clone());
             }
         }
@@ -213,13 +422,13 @@ impl Snippet {
                 break
             }
             let line = &file.lines[i];
-            if !line.is_present_at(&self.code_tag) {
+            if !self.is_context(line) {
                 continue;
             }
             checked_lines += 1;
 
             // Store the most precise preceding location we find.
-            if self.preceding_location.as_ref().map_or(true,|p| line.location.depth() > p.depth()) {
+            if self.preceding_location.as_ref().is_none_or(|p| line.location.depth() > p.depth()) {
                 self.preceding_location = Some(line.location.clone());
             }
         }
@@ -232,14 +441,14 @@ impl Snippet {
             if has_code_before {
                 break
             }
-            has_code_before = file.lines[i].is_present_at(&self.code_tag);
+            has_code_before = self.is_context(&file.lines[i]);
         }
 
         for i in self.last_line + 1..file.lines.len() {
             if has_code_after {
                 break
             }
-            has_code_after = file.lines[i].is_present_at(&self.code_tag);
+            has_code_after = self.is_context(&file.lines[i]);
         }
 
         if !has_code_before {
@@ -247,195 +456,269 @@ impl Snippet {
                 parent: self.location.as_ref().map(|x| Box::new(x.clone())),
                 kind: String::from(if has_code_after { "top" } else { "new" }),
                 name: None,
-                is_function_declaration: false
+                is_function_declaration: false,
+                signature: None
             });
         }
     }
 }
 
-struct SourceLine<'a> {
-    content: String,
-    location: Location,
-    start: &'a CodeTag,
-    end: Option<&'a CodeTag>
+pub struct SourceLine<'a> {
+    pub content: String,
+    pub location: Location,
+    pub start: &'a CodeTag,
+    pub end: Option<&'a CodeTag>
 }
 
 impl SourceLine<'_> {
-    fn is_present_at(&self, tag: &CodeTag) -> bool {
+    pub fn is_present_at(&self, tag: &CodeTag) -> bool {
         if tag.is_before(self.start) {
             return false
         }
-        if self.end.is_some() && tag.is_before(self.end.unwrap()).not() {
+        if self.end.is_some_and(|end| tag.is_before(end).not()) {
             return false
         } 
-        return true
+        true
+    }
+
+    /// Whether the line is present at the end of the chapter `chapter`.
+    pub fn is_present_after(&self, chapter: usize) -> bool {
+        self.start.chapter <= chapter && self.end.is_none_or(|end| end.chapter > chapter)
     }
 }
 
-struct SourceFile<'a> {
-    lines: Vec<SourceLine<'a>>
+pub struct SourceFile<'a> {
+    /// Path of the file, relative to the source root.
+    pub path: PathBuf,
+    pub lines: Vec<SourceLine<'a>>
+}
+
+/// A problem found in the code markers of a source file.
+pub struct MarkerError {
+    pub path: PathBuf,
+    pub line: usize,
+    pub message: String
+}
+
+impl fmt::Display for MarkerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.path.display(), self.line, self.message)
+    }
 }
 
 #[derive(Debug)]
 struct ParseState<'a> {
     start: &'a CodeTag,
-    end: Option<&'a CodeTag>
+    end: Option<&'a CodeTag>,
+    // The tag name as written in the marker, `start` may be a stand-in for an unknown tag.
+    name: String,
+    is_block: bool,
+    line: usize
+}
+
+/// Reads the lines of a source file one at a time, decoding the lines which
+/// are not valid UTF-8 instead of failing.
+struct SourceLines<'a, R> {
+    reader: R,
+    path: &'a Path,
+    line: usize,
+    latin1_fallback: bool
+}
+
+/// Replaces tabs with spaces up to the next multiple of `tab_width` columns.
+fn expand_tabs(line: &str, tab_width: usize) -> Cow<'_, str> {
+    if !line.contains('\t') {
+        return Cow::Borrowed(line)
+    }
+    let mut result = String::with_capacity(line.len());
+    let mut column = 0;
+    for c in line.chars() {
+        if c == '\t' {
+            let spaces = tab_width - column % tab_width;
+            result.extend(std::iter::repeat_n(' ', spaces));
+            column += spaces;
+        } else {
+            result.push(c);
+            column += 1;
+        }
+    }
+    Cow::Owned(result)
+}
+
+/// The line as seen by the language backends, which count two spaces per
+/// level of indentation: each leading tab is one level, whatever its width.
+fn tabs_as_levels(line: &str) -> Cow<'_, str> {
+    let tabs = line.len() - line.trim_start_matches('\t').len();
+    if tabs == 0 {
+        return Cow::Borrowed(line)
+    }
+    Cow::Owned(format!("{}{}", "  ".repeat(tabs), &line[tabs..]))
+}
+
+impl<R: BufRead> SourceLines<'_, R> {
+    fn decode(&self, bytes: Vec<u8>) -> String {
+        String::from_utf8(bytes).unwrap_or_else(|e| {
+            let bytes = e.into_bytes();
+            if self.latin1_fallback {
+                log::warn!("{}:{}: invalid UTF-8, decoded as Latin-1", self.path.display(), self.line);
+                bytes.iter().map(|&b| char::from(b)).collect()
+            } else {
+                log::warn!("{}:{}: invalid UTF-8, invalid bytes replaced", self.path.display(), self.line);
+                String::from_utf8_lossy(&bytes).into_owned()
+            }
+        })
+    }
+}
+
+impl<R: BufRead> Iterator for SourceLines<'_, R> {
+    type Item = Result<String, std::io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut bytes = Vec::new();
+        match self.reader.read_until(b'\n', &mut bytes) {
+            Ok(0) => None,
+            Ok(_) => {
+                self.line += 1;
+                if bytes.ends_with(b"\n") {
+                    bytes.pop();
+                    if bytes.ends_with(b"\r") {
+                        bytes.pop();
+                    }
+                }
+                Some(Ok(self.decode(bytes)))
+            }
+            Err(e) => Some(Err(e))
+        }
+    }
 }
 
-struct SourceFileParser<'a> {
+/// Parses the code markers of source files, tracking where each line goes.
+pub struct SourceFileParser<'a> {
     code_book: &'a CodeBook,
+    latin1_fallback: bool,
+    tab_width: Option<usize>,
     states: Vec<ParseState<'a>>,
-    location: Location
+    location: Location,
+    path: PathBuf,
+    line: usize,
+    errors: Vec<MarkerError>,
+    /// The backend of the file being parsed.
+    language: &'static dyn LanguageBackend
 }
 
 lazy_static!{
-    pub static ref START_RE: Regex = Regex::new("^//> ([A-Z][A-Za-z\\s]+\\s+)?([-a-z0-9]+)$").unwrap();
-    pub static ref END_RE: Regex = Regex::new("^//< ([A-Z][A-Za-z\\s]+\\s+)?([-a-z0-9]+)$").unwrap();
-    pub static ref START_BLOCK_RE: Regex = Regex::new("^/\\* ([A-Z][A-Za-z\\s]+) ([-a-z0-9]+) < ([A-Z][A-Za-z\\s]+) ([-a-z0-9]+)$").unwrap();
-
-    pub static ref CONSTRUCTOR_PATTERN: Regex = Regex::new("^  ([A-Z][a-z]\\w+)\\(").unwrap();
-    pub static ref FUNCTION_PATTERN: Regex = Regex::new("(\\w+)>*\\*? (\\w+)\\(([^)]*)").unwrap();
-    pub static ref VARIABLE_PATTERN: Regex = Regex::new("^\\w+\\*? (\\w+)(;| = )").unwrap();
-    pub static ref TYPE_PATTERN: Regex = Regex::new("(public )?(abstract )?(class|enum|interface) ([A-Z]\\w+).*").unwrap();
+    static ref START_RE: Regex = Regex::new("^//> ([A-Z][A-Za-z\\s]+\\s+)?([-a-z0-9]+)$").unwrap();
+    static ref END_RE: Regex = Regex::new("^//< ([A-Z][A-Za-z\\s]+\\s+)?([-a-z0-9]+)$").unwrap();
+    static ref START_BLOCK_RE: Regex = Regex::new("^/\\* ([A-Z][A-Za-z\\s]+) ([-a-z0-9]+) < ([A-Z][A-Za-z\\s]+) ([-a-z0-9]+)$").unwrap();
 
-    pub static ref KEYWORDS: Vec<&'static str> = vec!("new", "return", "throw");
-    
-    // pub static ref STRUCT_PATTERN: Regex = Regex::new("^struct (\\w+)? \\{$").unwrap();
-    // pub static ref NAMED_TYPEDEF_PATTERN: Regex = Regex::new("^typedef (enum|struct|union) (\\w+) \\{$").unwrap();
-    // pub static ref UNNAMED_TYPEDEF_PATTERN: Regex = Regex::new("^typedef (enum|struct|union) \\{$").unwrap();
-    // pub static ref TYPEDEF_NAME_PATTERN: Regex = Regex::new("^} (\\w+);$").unwrap();
 }
 
 impl<'x> SourceFileParser<'x> {
 
-    fn new<'a, 'b>(code_book: &'a CodeBook) -> SourceFileParser<'b> where 'a: 'b {
-        return SourceFileParser {
-            code_book: code_book,
+    pub fn new<'a, 'b>(code_book: &'a CodeBook, config: &Configuration) -> SourceFileParser<'b> where 'a: 'b {
+        SourceFileParser {
+            code_book,
+            latin1_fallback: config.latin1_fallback,
+            tab_width: config.tab_width,
             states: Vec::new(),
             location: Location {
                 parent: None,
                 kind: String::new(),
                 name: None,
-                is_function_declaration: false
-            }
+                is_function_declaration: false,
+                signature: None
+            },
+            path: PathBuf::new(),
+            line: 0,
+            errors: Vec::new(),
+            language: &Java
         }
     }
 
-    fn parse_source_file<'b>(&mut self, path: &Path, source_dir: &Path) -> Result<SourceFile<'b>, Error> where 'x: 'b {
+    /// The problems found in the code markers of the files parsed so far.
+    pub fn errors(&self) -> &[MarkerError] {
+        &self.errors
+    }
+
+    /// Parses the file at `path`, which lies under `source_dir`. Problems in
+    /// the code markers do not stop the parsing, they are kept in `errors`.
+    pub fn parse_source_file<'b>(&mut self, path: &Path, source_dir: &Path) -> Result<SourceFile<'b>, Error> where 'x: 'b {
         let relative_path = path.strip_prefix(source_dir).unwrap();
+        self.path = relative_path.to_path_buf();
+        self.language = language::backend_for(path).unwrap_or(&Java);
         // println!("SOURCE {}", relative_path.display());
         self.location = Location {
             parent: None,
             kind: String::from("file"),
             name: Some(String::from(relative_path.to_str().unwrap())),
-            is_function_declaration: false            
+            is_function_declaration: false,
+            signature: None
         };
 
         let input = File::open(path)?;
-        let buffered = BufReader::new(input);
+        let lines = SourceLines {
+            reader: BufReader::new(input),
+            path: relative_path,
+            line: 0,
+            latin1_fallback: self.latin1_fallback
+        };
         let mut source_file = SourceFile {
+            path: relative_path.to_path_buf(),
             lines: Vec::new()
         };
         
         self.states.clear();
-        let lines: Vec<String> = buffered.lines().map(|l|l.unwrap()).collect(); 
-        for (i, line) in lines.iter().enumerate() {
+        self.line = 0;
+        for line in lines {
+            let line = line?;
             // println!("LINE '{}'", line);
-            self.update_location_before(&line, lines.get(i+1));
-            if !self.update_state(line.as_str()) {
-                let state = self.states.last().unwrap();
-                source_file.lines.push(SourceLine {
-                    content: line.clone(),
-                    location: self.location.clone(),
-                    start: state.start,
-                    end: state.end
-                });
-            }
-            self.update_location_after(&line);
-        }
-        Ok(source_file)
-    }
-
-    fn update_location_before(&mut self, line: &String, next_line: Option<&String>) {
-        if let Some(c) = FUNCTION_PATTERN.captures(line) {
-            if !KEYWORDS.contains(&c.get(1).unwrap().as_str()) {
-                // Hack. Don't get caught by comments or string literals.
-                if !line.contains("//") && !line.contains('"') {
-                    let mut is_function_declaration = line.ends_with(";");
-
-                    // Hack: Handle multi-line declarations.
-                    if line.ends_with(",") && next_line.map_or(false, |nl|nl.ends_with(";")) {
-                        is_function_declaration = true
-                    }
-
-                    self.location = Location {
-                        parent: Some(Box::new(self.location.clone())),
-                        kind: String::from(if /*file.language == "java"*/ true { "method" } else { "function" }),
-                        name: Some(String::from(c.get(2).unwrap().as_str())),
-                        //signature = match.groups[3]!!.value,
-                        is_function_declaration
-                    };
-                    return
+            self.line += 1;
+            // The tab width only matters to the rendered lines, not to the scopes.
+            let scope_line = tabs_as_levels(&line);
+            let line = match self.tab_width {
+                Some(tab_width) => expand_tabs(&line, tab_width),
+                None => Cow::Borrowed(line.as_str())
+            };
+            let entered = match self.language.location_before(&self.location, &scope_line) {
+                Some(location) => {
+                    self.location = location;
+                    true
                 }
-            }
-        }
-        
-        if let Some(c) = CONSTRUCTOR_PATTERN.captures(line) {
-            self.location = Location {
-                parent: Some(Box::new(self.location.clone())),
-                kind: String::from("constructor"),
-                name: Some(String::from(c.get(1).unwrap().as_str())),
-                is_function_declaration: false
+                None => false
             };
-            return
-        }
-        if let Some(c) = TYPE_PATTERN.captures(line) {
-            // Hack. Don't get caught by comments or string literals.
-            if !line.contains("//") && !line.contains('"') {
-                self.location = Location {
-                    parent: Some(Box::from(self.location.clone())),
-                    kind: String::from(c.get(3).unwrap().as_str()),
-                    name: Some(String::from(c.get(4).unwrap().as_str())),
-                    is_function_declaration: false
-                };
+            if !self.update_state(&line) {
+                if let Some(state) = self.states.last() {
+                    source_file.lines.push(SourceLine {
+                        content: line.into_owned(),
+                        location: self.location.clone(),
+                        start: state.start,
+                        end: state.end
+                    });
+                } else {
+                    self.error(String::from("line is outside of any code tag"));
+                }
             }
-            return
+            self.location = self.language.location_after(&self.location, &scope_line, entered);
         }
-        if let Some(c) = VARIABLE_PATTERN.captures(line) {
-            self.location = Location{
-                parent: Some(Box::from(self.location.clone())),
-                kind: String::from("variable"),
-                name: Some(String::from(c.get(1).unwrap().as_str())),
-                is_function_declaration: false
-            };
-            return;
-        }
-    }
 
-    fn update_location_after(&mut self, line: &String) {
-        // Use "startsWith" to include lines like "} [aside-marker]".
-        if line.starts_with("}") {
-            self.location = self.location.pop_to_depth(0);
-        } else if line.starts_with("  }") {
-            self.location = self.location.pop_to_depth(1)
-        } else if line.starts_with("    }") {
-            self.location = self.location.pop_to_depth(2)
-        }
-
-        // If we reached a function declaration, not a definition, then it's done after one line.
-        if self.location.is_function_declaration {
-            self.location = *self.location.parent.clone().unwrap();
+        // The outermost marker may run until the end of the file, but not the nested ones.
+        while let Some(state) = self.states.pop() {
+            self.line = state.line;
+            if state.is_block {
+                self.error(format!("unterminated `/* ... */` block for `{}`", state.name));
+            } else if !self.states.is_empty() {
+                self.error(format!("`//>` marker for `{}` is never closed", state.name));
+            }
         }
+        Ok(source_file)
+    }
 
-        // Module variables are only a single line.
-        if self.location.kind == "variable" {
-            self.location = *self.location.parent.clone().unwrap();
-        }
+    fn error(&mut self, message: String) {
+        self.errors.push(MarkerError { path: self.path.clone(), line: self.line, message });
+    }
 
-        // Hack. There is a one-line class in Parser.java.
-        if line.contains("class ParseError") {
-            self.location = *self.location.parent.clone().unwrap();
-        }
+    fn chapter_name(&self, code_tag: &CodeTag) -> &'x str {
+        &self.code_book.chapters[code_tag.chapter].name
     }
 
     fn update_state(&mut self, line: &str) -> bool {
@@ -446,14 +729,23 @@ impl<'x> SourceFileParser<'x> {
         if let Some(c) = END_RE.captures(line) {
             // println!("END {}", line);
             let end_name = c.get(2).unwrap().as_str();
+            let Some(state) = self.states.last() else {
+                self.error(format!("`//<` marker for `{}` has no matching `//>` marker", end_name));
+                return true
+            };
+            let (start, name, is_block) = (state.start, state.name.clone(), state.is_block);
             if let Some(chapter_name) = c.get(1).map(|x|x.as_str().trim()) {
-                let test_chapter_name = &self.code_book.chapters[self.states.last().unwrap().start.chapter].name;
-                if test_chapter_name != "$static$" {
-                    assert_eq!(test_chapter_name, chapter_name)
+                let test_chapter_name = self.chapter_name(start);
+                if test_chapter_name != "$static$" && test_chapter_name != chapter_name {
+                    self.error(format!("`//<` marker names chapter `{}` but `{}` belongs to chapter `{}`",
+                        chapter_name, start.name, test_chapter_name));
                 }
             }
-            assert_eq!(self.states.last().unwrap().start.name, end_name);
-            assert!(self.states.len() > 0);
+            if is_block {
+                self.error(format!("`//<` marker for `{}` inside the `/* ... */` block for `{}`", end_name, name));
+            } else if name != end_name {
+                self.error(format!("`//<` marker for `{}` closes the `//>` marker for `{}`", end_name, name));
+            }
             self.pop();
             return true
         }
@@ -464,11 +756,12 @@ impl<'x> SourceFileParser<'x> {
                 Some((end_chapter.trim(), end_name)));
             return true;
         }
-        if line.trim() == "*/" {
+        // Only close blocks, a lone `*/` may also end an ordinary comment.
+        if line.trim() == "*/" && self.states.last().is_some_and(|s|s.is_block) {
             self.pop();
             return true
         }
-        return false;
+        false
     }
 
     fn pop(&mut self) {
@@ -480,216 +773,515 @@ impl<'x> SourceFileParser<'x> {
     }
 
     fn push(&mut self, start_chapter_name: Option<&str>, start_name: &str, end: Option<(&str, &str)>) {
-        let start_chapter_name = start_chapter_name.unwrap_or_else(|| {
-            let chapter_name = &self.code_book.chapters[self.states.last().unwrap().start.chapter].name;
-            // println!("DEFAULT CHAPTER '{}' / {}", chapter_name, start_name);
-            chapter_name
-        }).trim();
-        let start_code_tag = self.code_book.find_code_tag(start_chapter_name, start_name).unwrap_or_else(|| panic!("unknown start tag: [{:?}] {}/{}", self.location, start_chapter_name, start_name));
+        let start_chapter_name = match start_chapter_name {
+            Some(name) => name.trim(),
+            None => match self.states.last() {
+                // println!("DEFAULT CHAPTER '{}' / {}", chapter_name, start_name);
+                Some(state) => self.chapter_name(state.start),
+                None => {
+                    self.error(format!("`//>` marker for `{}` needs a chapter name outside of any code tag", start_name));
+                    ""
+                }
+            }
+        };
+        // Unknown tags are reported and replaced by `not-yet` so that parsing can go on.
+        let start_code_tag = self.find_code_tag(start_chapter_name, start_name);
 
         let end_code_tag = end.map(|(end_chapter_name, end_name)| {
-            self.code_book.find_code_tag(end_chapter_name, end_name).unwrap()
+            self.find_code_tag(end_chapter_name, end_name)
+        });
+        self.states.push(ParseState {
+            start: start_code_tag,
+            end: end_code_tag,
+            name: String::from(start_name),
+            is_block: end.is_some(),
+            line: self.line
         });
-        self.states.push(ParseState {  start: start_code_tag, end: end_code_tag });
         // print!("PUSH {:?}\n", self.states.last().unwrap());
     }
+
+    fn find_code_tag(&mut self, chapter_name: &str, name: &str) -> &'x CodeTag {
+        self.code_book.find_code_tag(chapter_name, name).unwrap_or_else(|| {
+            if !chapter_name.is_empty() {
+                self.error(format!("unknown code tag `{}/{}`", chapter_name, name));
+            }
+            self.code_book.chapters.last().unwrap().find_code_tag("not-yet").unwrap()
+        })
+    }
+}
+
+/// Removes the first `width` characters of `line`, which are all whitespace
+/// unless the line is blank.
+fn strip_indentation(line: &str, width: usize) -> &str {
+    line.char_indices().nth(width).map_or("", |(i, _)| &line[i..])
+}
+
+fn report_errors(errors: &[MarkerError], what: &str) -> Result<(), Error> {
+    if errors.is_empty() {
+        return Ok(())
+    }
+    for error in errors {
+        log::error!("{}", error);
+    }
+    Err(Error::msg(format!("found {} problems in {}", errors.len(), what)))
+}
+
+enum ParsedSource<'a> {
+    Cached(SourceFile<'a>),
+    Parsed(SourceFile<'a>, Vec<MarkerError>, Option<serde_json::Value>)
 }
 
 #[derive(Default)]
-pub(crate) struct CodeTagsHighlighterPreprocessor;
+pub struct CodeTagsHighlighterPreprocessor;
+
+/// The error `message` about the directive matched by `directive` in `chapter`.
+fn directive_error(chapter: &mdbook::book::Chapter, directive: &regex::Captures, message: String) -> MarkerError {
+    MarkerError {
+        path: chapter.source_path.clone().unwrap_or_default(),
+        line: chapter.content[..directive.get(0).unwrap().start()].matches('\n').count() + 1,
+        message
+    }
+}
 
-const CODETAG_RE_STR: &str = r"(?m)^\^code\s+([a-z-]+)\s*(?:\(([^)]*)\))?";
+/// `^code tag (options)` directives, whose options may hold parentheses
+/// within double quotes.
+const CODETAG_RE_STR: &str = r#"(?m)^\^code\s+([a-z-]+)\s*(?:\(((?:[^)"]|"[^"]*")*)\))?"#;
+/// `^ref Chapter/tag` directives, showing again the snippet of another chapter.
+const REF_RE_STR: &str = r"(?m)^\^ref\s+(.+)/([a-z-]+)\s*$";
+/// `^file path (options)` directives, showing a whole source file.
+const FILE_RE_STR: &str = r#"(?m)^\^file\s+(\S+)\s*(?:\(((?:[^)"]|"[^"]*")*)\))?"#;
+/// Link targets of the `[text](codetag:Chapter/tag)` cross references.
+const CROSS_REF_RE_STR: &str = r"\]\(codetag:([^)]*)\)";
     
 impl CodeTagsHighlighterPreprocessor {
 
-    fn collect_code_tags(&self, book: &mdbook::book::Book) -> CodeBook {
+    pub fn configuration(&self, config: &mdbook::Config) -> Configuration {
+        let mut result: Configuration = match config.get_preprocessor(self.name()) {
+            Some(c) => c.try_into().unwrap(),
+            None => Configuration::default(),
+        };
+        result.captions.language = config.book.language.clone();
+        result
+    }
+
+    pub fn source_dir(&self, root: &Path, config: &Configuration) -> PathBuf {
+        if config.src_root.is_relative() {
+            root.join(&config.src_root)
+        } else {
+            config.src_root.clone()
+        }
+    }
+
+    /// Collects the code tags of the `^code` directives of every chapter,
+    /// reporting invalid directive options into `errors`.
+    pub fn collect_code_tags(&self, book: &mdbook::book::Book, errors: &mut Vec<MarkerError>) -> CodeBook {
         let codetag_re = Regex::new(CODETAG_RE_STR).unwrap();
 
         let mut chapters: Vec<Chapter> = Vec::new();
+        // Solutions belong to the static chapter, so that later chapters never show their code.
+        let mut solutions: Vec<(String, CodeTagOptions, usize)> = Vec::new();
     
         for item in book.iter() { 
             if let BookItem::Chapter(chapter) = item {
-                let mut index = 0;
-                
-                for c in codetag_re.captures_iter(&chapter.content) {
+                for (index, c) in codetag_re.captures_iter(&chapter.content).enumerate() {
                     let id = c.get(1).unwrap().as_str();
-                    let mut no_location = false;
-                    let mut before_count = 0;
-                    let mut after_count = 0;
-                    c.get(2)
-                        .map(|x|x.as_str()).unwrap_or("")
-                        .split(",")
-                        .map(|x|x.trim())
-                        .for_each(|opt|{
-                        if opt == "no location" {
-                            no_location = true
-                        } else if opt.ends_with(" before") {
-                            before_count = opt[..opt.len()-6].trim().parse().unwrap();
-                        } else if opt.ends_with(" after") {
-                            after_count = opt[..opt.len()-5].trim().parse().unwrap();
-                        }
-                    });
+                    let options = CodeTagOptions::parse(c.get(2).map(|x|x.as_str()).unwrap_or(""))
+                        .unwrap_or_else(|message| {
+                            errors.push(directive_error(chapter, &c, message));
+                            CodeTagOptions::default()
+                        });
 
                     let chapter_index = if let Some(i) = chapters.iter().position(|c|c.name == chapter.name) {
                         i
                     } else {
                         chapters.push(Chapter {
-                            name: String::from(chapter.name.clone()),
+                            name: chapter.name.clone(),
+                            path: chapter.path.clone(),
                             code_tags: Vec::new()
                         });
                         chapters.len() - 1
                     };
 
+                    // Solutions are looked up before the tags of any chapter, so their names must be unique.
+                    if options.solution {
+                        if solutions.iter().any(|(name, _, _)| name == id) {
+                            errors.push(directive_error(chapter, &c, format!("solution `{}` is already defined", id)));
+                        } else if let Some(clash) = chapters.iter().find(|c| c.find_code_tag(id).is_some()) {
+                            errors.push(directive_error(chapter, &c, format!("solution `{}` has the name of code tag `{}/{}`", id, clash.name, id)));
+                        } else if id == "omit" || id == "not-yet" {
+                            errors.push(directive_error(chapter, &c, format!("solution `{}` has a reserved name", id)));
+                        }
+                        solutions.push((String::from(id), options, chapter_index));
+                        continue
+                    }
+                    if solutions.iter().any(|(name, _, _)| name == id) {
+                        errors.push(directive_error(chapter, &c, format!("code tag `{}` has the name of a solution", id)));
+                    }
                     chapters[chapter_index].code_tags.push(CodeTag {
                         chapter: chapter_index,
                         name: String::from(id),
-                        index: index,
-                        no_location: no_location,
-                        before_count: before_count,
-                        after_count: after_count
+                        index: index as u32,
+                        options,
+                        exercise: None
                     });
-                    index += 1;
                 }
             }
         }
-        chapters.push(Chapter { name: String::from("$static$"), code_tags: vec![
-            CodeTag { chapter: chapters.len(), name: String::from("omit"), index: 9998, before_count: 0, after_count: 0, no_location: false },
-            CodeTag { chapter: chapters.len(), name: String::from("not-yet"), index: 9999, before_count: 0, after_count: 0, no_location: false }
-        ] });
-        return CodeBook { chapters: chapters };
+        let static_index = chapters.len();
+        let mut static_tags: Vec<CodeTag> = solutions.into_iter().enumerate().map(|(index, (name, options, exercise))| {
+            CodeTag { chapter: static_index, name, index: index as u32, options, exercise: Some(exercise) }
+        }).collect();
+        static_tags.push(CodeTag { chapter: static_index, name: String::from("omit"), index: 9998, options: CodeTagOptions::default(), exercise: None });
+        static_tags.push(CodeTag { chapter: static_index, name: String::from("not-yet"), index: 9999, options: CodeTagOptions::default(), exercise: None });
+        chapters.push(Chapter { name: String::from("$static$"), path: None, code_tags: static_tags });
+        let code_book = CodeBook { chapters };
+
+        let ref_re = Regex::new(REF_RE_STR).unwrap();
+        let file_re = Regex::new(FILE_RE_STR).unwrap();
+        let cross_ref_re = Regex::new(CROSS_REF_RE_STR).unwrap();
+        for item in book.iter() {
+            if let BookItem::Chapter(chapter) = item {
+                for c in file_re.captures_iter(&chapter.content) {
+                    let result = FileOptions::parse(c.get(2).map(|x|x.as_str()).unwrap_or(""))
+                        .and_then(|options| self.file_point(&code_book, &chapter.name, &options));
+                    if let Err(message) = result {
+                        errors.push(directive_error(chapter, &c, message));
+                    }
+                }
+                for c in ref_re.captures_iter(&chapter.content) {
+                    let (chapter_name, name) = (c[1].trim(), &c[2]);
+                    if code_book.find_code_tag(chapter_name, name).is_none() {
+                        errors.push(directive_error(chapter, &c, format!("reference to unknown snippet `{}/{}`", chapter_name, name)));
+                    }
+                }
+                for c in cross_ref_re.captures_iter(&chapter.content) {
+                    let point = c.get(1).unwrap().as_str();
+                    if code_book.find_point(point).is_none_or(|t| code_book.chapters[t.chapter].path.is_none()) {
+                        errors.push(directive_error(chapter, &c, format!("cross reference to unknown snippet `{}`", point)));
+                    }
+                }
+            }
+        }
+        code_book
     }
 
-    
+    /// The point a `^file` directive of `chapter_name` shows its file at: the
+    /// `at` option, or else the end of the chapter.
+    fn file_point<'a>(&self, code_book: &'a CodeBook, chapter_name: &str, options: &FileOptions) -> Result<&'a CodeTag, String> {
+        match &options.at {
+            Some(at) => code_book.find_point(at).ok_or_else(|| format!("unknown point `{}`", at)),
+            None => code_book.find_point(chapter_name)
+                .ok_or_else(|| String::from("option `at` is required in chapters without code tags"))
+        }
+    }
+
+    /// Reports the `^file` directives of `book` showing none of `source_files`,
+    /// and the `file` options of `^code` directives naming none of the files
+    /// of their code tag.
+    pub fn check_file_paths(&self, book: &mdbook::book::Book, code_book: &CodeBook, source_files: &[SourceFile], errors: &mut Vec<MarkerError>) {
+        let codetag_re = Regex::new(CODETAG_RE_STR).unwrap();
+        let file_re = Regex::new(FILE_RE_STR).unwrap();
+        for item in book.iter() {
+            if let BookItem::Chapter(chapter) = item {
+                for c in codetag_re.captures_iter(&chapter.content) {
+                    let Some(code_tag) = code_book.find_code_tag(&chapter.name, &c[1]) else { continue };
+                    let Some(file) = code_tag.options.file.as_deref() else { continue };
+                    let tagged = source_files.iter()
+                        .filter(|f| f.path.ends_with(file))
+                        .any(|f| f.lines.iter().any(|l| l.start == code_tag || l.end == Some(code_tag)));
+                    if !tagged {
+                        errors.push(directive_error(chapter, &c, format!("code tag `{}` is in no source file `{}`", code_tag.name, file)));
+                    }
+                }
+                for c in file_re.captures_iter(&chapter.content) {
+                    let path = Path::new(&c[1]);
+                    if !source_files.iter().any(|f| f.path == path) {
+                        errors.push(directive_error(chapter, &c, format!("unknown source file `{}`", path.display())));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Warns when `mdbook serve` would not rebuild the book on changes of the
+    /// sources, which happens unless the source root is covered by the
+    /// `build.extra-watch-dirs` setting.
+    fn check_source_dir_watched(&self, root: &Path, config: &Configuration, build: &mdbook::config::BuildConfig) {
+        let source_dir = self.source_dir(root, config);
+        let Ok(source_dir) = source_dir.canonicalize() else {
+            log::warn!("source root {} does not exist", source_dir.display());
+            return
+        };
+        let watched = build.extra_watch_dirs.iter()
+            .filter_map(|dir| root.join(dir).canonicalize().ok())
+            .any(|dir| source_dir.starts_with(dir));
+        if !watched {
+            log::warn!("source root {} is not watched by `mdbook serve`, add it to `build.extra-watch-dirs` in book.toml \
+                to rebuild the book when the sources change", source_dir.display());
+        }
+    }
+
+    /// Collects the code tags of the book, failing when any directive is wrong.
+    pub fn collect_valid_code_tags(&self, book: &mdbook::book::Book) -> Result<CodeBook, Error> {
+        let mut errors = Vec::new();
+        let code_book = self.collect_code_tags(book, &mut errors);
+        report_errors(&errors, "directives")?;
+        Ok(code_book)
+    }
+
+    /// Gathers the lines added and removed by each code tag, along with
+    /// their context, indexed by code tag name. A code tag has a snippet for
+    /// each source file it changes, in path order.
+    pub fn build_snippets<'a>(&self, source_files: &'a [SourceFile]) -> HashMap<&'a str, Vec<Snippet>> {
+        let mut snippets: HashMap<&str, Vec<Snippet>> = HashMap::new();
+
+        for source_file in source_files {
+            // Chapters may use the same tag name, each for its own snippet.
+            let mut local_snippets: HashMap<&CodeTag, Snippet> = HashMap::new();
+            for (line_index, line) in source_file.lines.iter().enumerate() {
+                local_snippets.entry(line.start)
+                    .or_insert_with(|| Snippet::new(line.start, &source_file.path))
+                    .add_line(line_index, line);
+
+                if let Some(end) = line.end {
+                    local_snippets.entry(end)
+                        .or_insert_with(|| Snippet::new(end, &source_file.path))
+                        .remove_line(line_index, line);
+                }
+            }
+            for snippet in local_snippets.values_mut() {
+                snippet.compute_context(source_file);
+                snippet.compute_overloads(source_file);
+            }
+            for (code_tag, snippet) in local_snippets {
+                snippets.entry(code_tag.name.as_str()).or_default().push(snippet);
+            }
+        }
+        snippets
+    }
+
+    /// Parses every source file found under the source root, collecting the
+    /// problems found in their code markers into `errors`.
+    ///
+    /// Files are parsed in parallel, but the result is always sorted by path.
+    /// Files left unchanged since the previous build are taken from the cache.
+    pub fn parse_sources<'a>(&self, code_book: &'a CodeBook, root: &Path, config: &Configuration, errors: &mut Vec<MarkerError>) -> Result<Vec<SourceFile<'a>>, Error> {
+        let source_dir = &self.source_dir(root, config);
+        let paths: Vec<PathBuf> = WalkDir::new(source_dir)
+                .sort_by_file_name()
+                .into_iter()
+                .filter_map(|e| e.ok())
+                // .filter(|e| e.path().file_name().unwrap() == "Lox.java")
+                .filter(|e| e.metadata().unwrap().is_file() && language::backend_for(e.path()).is_some())
+                .map(|e| e.into_path())
+                .collect();
+
+        let cache_file = config.cache_file.as_ref().map(|f| root.join(f));
+        let settings = format!("{} {} {:?}", source_dir.display(), config.latin1_fallback, config.tab_width);
+        let mut cache = cache_file.as_ref().map(|f| SourceCache::load(f, code_book, &settings));
+
+        let thread_count = thread::available_parallelism().map_or(1, |n| n.get()).min(paths.len()).max(1);
+        let next_path = AtomicUsize::new(0);
+        let shared_cache = cache.as_ref();
+        let mut results: Vec<_> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..thread_count).map(|_| scope.spawn(|| {
+                let mut parsed = Vec::new();
+                while let Some(path) = paths.get(next_path.fetch_add(1, Ordering::Relaxed)) {
+                    let stamp = cache::file_stamp(path);
+                    let relative_path = path.strip_prefix(source_dir).unwrap();
+                    if let Some(source_file) = shared_cache.zip(stamp.as_ref()).and_then(|(c, s)| c.get(relative_path, s)) {
+                        parsed.push((path, Ok(ParsedSource::Cached(source_file))));
+                        continue
+                    }
+                    let mut parser = SourceFileParser::new(code_book, config);
+                    let result = parser.parse_source_file(path, source_dir);
+                    parsed.push((path, result.map(|source_file| ParsedSource::Parsed(source_file, parser.errors, stamp))));
+                }
+                parsed
+            })).collect();
+            workers.into_iter().flat_map(|w| w.join().unwrap()).collect()
+        });
+
+        results.sort_by_key(|(path, _)| *path);
+        let mut source_files = Vec::new();
+        for (_, result) in results {
+            let source_file = match result? {
+                ParsedSource::Cached(source_file) => {
+                    if let Some(cache) = &mut cache {
+                        cache.keep(&source_file.path);
+                    }
+                    source_file
+                }
+                ParsedSource::Parsed(source_file, mut file_errors, stamp) => {
+                    // Files with errors are parsed again to report them on each build.
+                    if let (Some(cache), Some(stamp), true) = (&mut cache, stamp, file_errors.is_empty()) {
+                        cache.insert(&source_file, stamp);
+                    }
+                    errors.append(&mut file_errors);
+                    source_file
+                }
+            };
+            source_files.push(source_file);
+        }
+
+        if let (Some(cache), Some(cache_file)) = (&cache, &cache_file)
+            && let Err(e) = cache.save(cache_file) {
+            log::warn!("could not write cache file {}: {}", cache_file.display(), e);
+        }
+        Ok(source_files)
+    }
+
+    /// Parses every source file found under the source root, failing when any
+    /// code marker is wrong.
+    pub fn parse_valid_sources<'a>(&self, code_book: &'a CodeBook, root: &Path, config: &Configuration) -> Result<Vec<SourceFile<'a>>, Error> {
+        let mut errors = Vec::new();
+        let source_files = self.parse_sources(code_book, root, config, &mut errors)?;
+        report_errors(&errors, "code markers")?;
+        Ok(source_files)
+    }
+
+    /// Looks up the snippet shown by a `^code name` directive of `chapter`.
+    pub fn find_snippet<'a>(&self, code_book: &CodeBook, snippets: &'a HashMap<&str, Vec<Snippet>>, chapter: &str, name: &str) -> Option<&'a Snippet> {
+        let code_tag = code_book.find_code_tag(chapter, name)?;
+        let candidates = snippets.get(name)?.iter().filter(|s| s.code_tag == *code_tag);
+        select_snippet(candidates, code_tag.options.file.as_deref())
+    }
+
+    /// Validates the directives of the book and the code markers of the
+    /// sources, returning every problem found, including code tags which
+    /// appear in no source file.
+    pub fn check(&self, book: &mdbook::book::Book, root: &Path, config: &Configuration) -> Result<Vec<String>, Error> {
+        let mut errors = Vec::new();
+        let code_book = self.collect_code_tags(book, &mut errors);
+        let source_files = self.parse_sources(&code_book, root, config, &mut errors)?;
+        self.check_file_paths(book, &code_book, &source_files, &mut errors);
+        let mut problems: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+
+        let used: HashSet<_> = source_files.iter()
+            .flat_map(|f| &f.lines)
+            .flat_map(|l| [Some(l.start), l.end])
+            .flatten()
+            .collect();
+        // The last chapter only holds the static tags.
+        for chapter in &code_book.chapters[..code_book.chapters.len() - 1] {
+            for code_tag in chapter.code_tags.iter().filter(|t| !used.contains(t)) {
+                problems.push(format!("code tag `{}/{}` does not appear in any source file", chapter.name, code_tag.name));
+            }
+        }
+        Ok(problems)
+    }
 }
 
-impl Preprocessor for CodeTagsHighlighterPreprocessor {
-    fn name(&self) -> &str {
-        "codetags"
+/// The link from the chapter at `from` to the snippet of `code_tag`. The link
+/// points to the Markdown file of the chapter, which renderers translate.
+fn cross_ref_link(code_book: &CodeBook, from: Option<&Path>, code_tag: &CodeTag) -> String {
+    let anchor = code_book.anchor(code_tag);
+    let Some(to) = code_book.chapters[code_tag.shown_in()].path.as_deref() else {
+        return format!("#{}", anchor)
+    };
+    if from == Some(to) {
+        return format!("#{}", anchor)
+    }
+    let from_dir = from.and_then(Path::parent).unwrap_or(Path::new(""));
+    let common = from_dir.components().zip(to.components()).take_while(|(a, b)| a == b).count();
+    let mut link = String::new();
+    for _ in from_dir.components().skip(common) {
+        link.push_str("../");
     }
+    let rest: Vec<_> = to.components().skip(common).map(|c| c.as_os_str().to_string_lossy()).collect();
+    link.push_str(&rest.join("/"));
+    format!("{}#{}", link, anchor)
+}
 
-    fn supports_renderer(&self, renderer: &str) -> bool {
-        renderer == "html"
+/// Picks the snippet of the given source file, or of the last one in path order.
+fn select_snippet<'a>(mut snippets: impl Iterator<Item = &'a Snippet>, file: Option<&str>) -> Option<&'a Snippet> {
+    match file {
+        Some(file) => snippets.find(|s| s.file.ends_with(file)),
+        None => snippets.last()
     }
+}
 
-    fn run(&self, ctx: &mdbook::preprocess::PreprocessorContext, mut book: mdbook::book::Book) -> mdbook::errors::Result<mdbook::book::Book> {
+impl CodeTagsHighlighterPreprocessor {
+    /// Replaces the directives of `book`, whose root directory is `root`,
+    /// with the output of `renderer`. This is what the preprocessor does, with
+    /// the built-in renderer matching the mdbook renderer.
+    pub fn render_book(&self, root: &Path, mdbook_config: &mdbook::Config, mut book: mdbook::book::Book, renderer: &dyn SnippetRenderer) -> Result<mdbook::book::Book, Error> {
         
-        let config: Configuration = match ctx.config.get_preprocessor(self.name()) {
-            Some(c) => c.try_into().unwrap(),
-            None => Configuration::default(),
-        };
+        let config = self.configuration(mdbook_config);
+        self.check_source_dir_watched(root, &config, &mdbook_config.build);
 
-        let code_book = self.collect_code_tags(&book);
+        let code_book = self.collect_valid_code_tags(&book)?;
         
         // // <debug>
         // let mut file = std::fs::File::create("dump.txt").unwrap();
         // for chapter in &code_book.chapters {
         //     for code_tag in &chapter.code_tags {
-        //         let xx = format!("CODETAG '{}' / {} / {} / {} / {}\n", code_book.chapters[code_tag.chapter].name, code_tag.name, code_tag.no_location, code_tag.before_count, code_tag.after_count);
+        //         let xx = format!("CODETAG '{}' / {} / {} / {} / {}\n", code_book.chapters[code_tag.chapter].name, code_tag.name, code_tag.options.no_location, code_tag.options.before_count, code_tag.options.after_count);
         //         file.write_all(xx.as_bytes()).unwrap();
         //     }
         // }
         // file.flush().unwrap();
         // // </debug>
 
-        let source_dir = &if config.src_root.is_relative() {
-            ctx.root.join(config.src_root)
-        } else {
-            config.src_root
-        };
-
-        let mut snippets: HashMap<&str, Snippet> = HashMap::new();
-
-        for entry in WalkDir::new(source_dir)
-                .into_iter()
-                .filter_map(|e| e.ok())
-                // .filter(|e| e.path().file_name().unwrap() == "Lox.java")
-                .filter(|e| e.metadata().unwrap().is_file() && e.path().extension().and_then(OsStr::to_str).unwrap() == "java") {
-            let path = entry.path();
-            // let metadata = entry.metadata()?;
-            // let modified = metadata.modified()?.elapsed()?.as_secs();
-            // file.write_all(format!("SOURCE {}\n", path.display()).as_bytes())?;
-            
-            let mut parser = SourceFileParser::new(&code_book);
-            let source_file = parser.parse_source_file(path, source_dir).unwrap();
-            let mut local_snippets: HashMap<&str, Snippet> = HashMap::new();
-            for (line_index, line) in source_file.lines.iter().enumerate() {
-                let start_name = line.start.name.as_str();
-                if !local_snippets.contains_key(start_name) {
-                    local_snippets.insert(start_name, Snippet::new(line.start));
-                }
-                let snippet = local_snippets.get_mut(start_name).unwrap();
-                snippet.add_line(line_index, line);
-
-                if let Some(end) = line.end {
-                    let end_name = end.name.as_str();
-                    if !local_snippets.contains_key(end_name) {
-                        local_snippets.insert(end_name, Snippet::new(end));
-                    }
-                    let snippet = local_snippets.get_mut(end_name).unwrap();
-                    snippet.remove_line(line_index, line);
-                }
-            }
-            for (_, snippet) in &mut local_snippets {
-                snippet.compute_context(&source_file);
-            }
-            snippets.extend(local_snippets);
-        }
+        let source_files = self.parse_valid_sources(&code_book, root, &config)?;
+        let mut errors = Vec::new();
+        self.check_file_paths(&book, &code_book, &source_files, &mut errors);
+        report_errors(&errors, "directives")?;
+        let snippets = self.build_snippets(&source_files);
 
         // // <debug>
         // file.flush()?;
         // // </debug>
 
         let codetag_re = Regex::new(CODETAG_RE_STR).unwrap();
+        let ref_re = Regex::new(REF_RE_STR).unwrap();
+        let file_re = Regex::new(FILE_RE_STR).unwrap();
+        let cross_ref_re = Regex::new(CROSS_REF_RE_STR).unwrap();
         book.for_each_mut(|item| {
             if let BookItem::Chapter(chapter) = item {
                 let mut updated_content = String::with_capacity(chapter.content.len());
                 for line in chapter.content.lines() {
-                    if let Some(m) = codetag_re.captures(&line) {
+                    if let Some(m) = codetag_re.captures(line) {
                         let id = m.get(1).unwrap().as_str();
-                        if let Some(snippet) = snippets.get(id) {
-                            updated_content.push_str("<pre>");
-                            updated_content.push_str("<code class=\"language-java\">");
-                            for line in &snippet.context_before {
-                                updated_content.push_str("  ");
-                                updated_content.push_str(line);
-                                updated_content.push('\n');
-                            }
-                            for line in &snippet.removed {
-                                updated_content.push_str("- ");
-                                updated_content.push_str(line);
-                                updated_content.push('\n');
-                            }
-                            for line in &snippet.added {
-                                updated_content.push_str("+ ");
-                                updated_content.push_str(line);
-                                updated_content.push('\n');
-                            }
-                            for line in &snippet.context_after {
-                                updated_content.push_str("  ");
-                                updated_content.push_str(line);
-                                updated_content.push('\n');
-                            }
-                            updated_content.push_str("</code>\n");
-                            if let Some(location) = &snippet.location {
-                                updated_content.push_str("<div class=\"location\">");
-                                // updated_content.push_str(format!("<div>{:?}</div> <div>{:?}</div><br>", snippet.preceding_location, snippet.location).as_str());
-                                for (index, line) in location.to_html(
-                                    snippet.preceding_location.as_ref(),
-                                    !snippet.removed.is_empty()
-                                ).iter().enumerate() {
-                                    if index > 0 {
-                                        updated_content.push_str(", ");
-                                    }
-                                    updated_content.push_str(line);
-                                }
-                                updated_content.push_str("</div>\n");
-                            }
-                            updated_content.push_str("</pre>\n");
+                        let code_tag = code_book.find_code_tag(&chapter.name, id);
+                        if let (Some(code_tag), Some(snippet)) = (code_tag, self.find_snippet(&code_book, &snippets, &chapter.name, id)) {
+                            // The anchor is the one of the directive, which links to `Chapter/tag` point to.
+                            let anchor = code_book.anchor(code_tag);
+                            let caption = snippet.caption(&config);
+                            renderer.render_snippet(snippet, &anchor, caption.as_ref(), &config, &mut updated_content);
                         } else {
-                            updated_content.push_str(format!("<p>Code tag {} not found</p>\n", id).as_str());
+                            renderer.render_message(&format!("Code tag {} not found", id), &mut updated_content);
+                        }
+                    } else if let Some(m) = ref_re.captures(line) {
+                        let (chapter_name, id) = (m[1].trim(), &m[2]);
+                        // References were checked while collecting the code tags.
+                        let code_tag = code_book.find_code_tag(chapter_name, id);
+                        if let (Some(code_tag), Some(snippet)) = (code_tag, self.find_snippet(&code_book, &snippets, chapter_name, id)) {
+                            let link = cross_ref_link(&code_book, chapter.path.as_deref(), code_tag);
+                            renderer.render_reference(snippet, chapter_name, &link, &mut updated_content);
+                        } else {
+                            renderer.render_message(&format!("Code tag {}/{} not found", chapter_name, id), &mut updated_content);
+                        }
+                    } else if let Some(m) = file_re.captures(line) {
+                        let path = Path::new(&m[1]);
+                        // Options, points and paths were checked before.
+                        let options = FileOptions::parse(m.get(2).map(|x|x.as_str()).unwrap_or("")).unwrap_or_default();
+                        let point = self.file_point(&code_book, &chapter.name, &options);
+                        let source_file = source_files.iter().find(|f| f.path == path);
+                        if let (Ok(point), Some(source_file)) = (point, source_file) {
+                            renderer.render_file(source_file, point, &options, &mut updated_content);
+                        } else {
+                            renderer.render_message(&format!("Source file {} not found", path.display()), &mut updated_content);
                         }
                     } else {
-                        updated_content.push_str(line);
+                        // Cross references were checked while collecting the code tags.
+                        let line = cross_ref_re.replace_all(line, |c: &regex::Captures| {
+                            match code_book.find_point(&c[1]) {
+                                Some(code_tag) => format!("]({})", cross_ref_link(&code_book, chapter.path.as_deref(), code_tag)),
+                                None => String::from(&c[0])
+                            }
+                        });
+                        updated_content.push_str(&line);
                         updated_content.push('\n');
                     }
                 }
@@ -699,4 +1291,127 @@ impl Preprocessor for CodeTagsHighlighterPreprocessor {
 
         Ok(book)
     }
-}
\ No newline at end of file
+}
+
+impl Preprocessor for CodeTagsHighlighterPreprocessor {
+    fn name(&self) -> &str {
+        "codetags"
+    }
+
+    fn supports_renderer(&self, renderer: &str) -> bool {
+        renderer_for(renderer, &Configuration::default()).is_some()
+    }
+
+    fn run(&self, ctx: &mdbook::preprocess::PreprocessorContext, book: mdbook::book::Book) -> mdbook::errors::Result<mdbook::book::Book> {
+        let config = self.configuration(&ctx.config);
+        let renderer = renderer_for(&ctx.renderer, &config).unwrap_or_else(|| Box::new(HtmlRenderer));
+        self.render_book(&ctx.root, &ctx.config, book, renderer.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chapters_sharing_a_tag_name_have_their_own_snippets() {
+        let code_tag = |chapter, name: &str| CodeTag { chapter, name: String::from(name), index: 0, options: CodeTagOptions::default(), exercise: None };
+        let code_book = CodeBook { chapters: vec![
+            Chapter { name: String::from("Alpha"), path: None, code_tags: vec![code_tag(0, "main")] },
+            Chapter { name: String::from("Beta"), path: None, code_tags: vec![code_tag(1, "main")] },
+            Chapter { name: String::from("$static$"), path: None, code_tags: Vec::new() }
+        ] };
+        let location = Location { parent: None, kind: String::from("file"), name: None, is_function_declaration: false, signature: None };
+        let line = |content: &str, start| SourceLine { content: String::from(content), location: location.clone(), start, end: None };
+        let (alpha, beta) = (&code_book.chapters[0].code_tags[0], &code_book.chapters[1].code_tags[0]);
+        let source_files = vec![SourceFile { path: PathBuf::from("Main.java"), lines: vec![line("alpha", alpha), line("beta", beta)] }];
+
+        let pre = CodeTagsHighlighterPreprocessor;
+        let snippets = pre.build_snippets(&source_files);
+        let added = |chapter| pre.find_snippet(&code_book, &snippets, chapter, "main").map(|s| s.added.clone());
+        assert_eq!(added("Alpha"), Some(vec![String::from("alpha")]));
+        assert_eq!(added("Beta"), Some(vec![String::from("beta")]));
+    }
+
+    #[test]
+    fn solutions_are_shown_at_the_end_of_their_exercise_chapter() {
+        let code_tag = |chapter, name: &str| CodeTag { chapter, name: String::from(name), index: 0, options: CodeTagOptions::default(), exercise: None };
+        let options = CodeTagOptions { before_count: 1, after_count: 1, solution: true, ..CodeTagOptions::default() };
+        let solution = CodeTag { chapter: 2, name: String::from("sol"), index: 0, options, exercise: Some(0) };
+        let code_book = CodeBook { chapters: vec![
+            Chapter { name: String::from("Alpha"), path: None, code_tags: vec![code_tag(0, "main")] },
+            Chapter { name: String::from("Beta"), path: None, code_tags: vec![code_tag(1, "main")] },
+            Chapter { name: String::from("$static$"), path: None, code_tags: vec![solution] }
+        ] };
+        let location = Location { parent: None, kind: String::from("file"), name: None, is_function_declaration: false, signature: None };
+        let line = |content: &str, start| SourceLine { content: String::from(content), location: location.clone(), start, end: None };
+        let tags: Vec<&CodeTag> = code_book.chapters.iter().map(|c| &c.code_tags[0]).collect();
+        let source_files = vec![SourceFile { path: PathBuf::from("Main.java"), lines: vec![line("alpha", tags[0]), line("sol", tags[2]), line("beta", tags[1])] }];
+
+        let pre = CodeTagsHighlighterPreprocessor;
+        let snippets = pre.build_snippets(&source_files);
+        let snippet = pre.find_snippet(&code_book, &snippets, "Alpha", "sol").unwrap();
+        assert_eq!(snippet.context_before, vec![String::from("alpha")]);
+        assert!(snippet.context_after.is_empty());
+        assert_eq!(code_book.anchor(tags[2]), "alpha-sol");
+    }
+
+    #[test]
+    fn tabs_are_one_level_whatever_their_width() {
+        let code_book = CodeBook { chapters: vec![Chapter {
+            name: String::from("Scanning"),
+            path: None,
+            code_tags: vec![CodeTag { chapter: 0, name: String::from("tabs"), index: 0, options: CodeTagOptions::default(), exercise: None }]
+        }] };
+        let source_dir = std::env::temp_dir().join(format!("codetags-tabs-{}", std::process::id()));
+        std::fs::create_dir_all(&source_dir).unwrap();
+        let path = source_dir.join("Main.java");
+        std::fs::write(&path, "//> Scanning tabs\nclass Main {\n\tvoid run() {\n\t\tint a = 1;\n\t}\n\tvoid other() {\n\t\tint b = 2;\n\t}\n}\n").unwrap();
+
+        let config = Configuration { tab_width: Some(4), ..Configuration::default() };
+        let mut parser = SourceFileParser::new(&code_book, &config);
+        let source_file = parser.parse_source_file(&path, &source_dir).unwrap();
+        std::fs::remove_dir_all(&source_dir).unwrap();
+
+        let line = &source_file.lines[5];
+        assert_eq!(line.content, "        int b = 2;");
+        let names: Vec<&str> = line.location.chain().iter().filter_map(|l| l.name.as_deref()).collect();
+        assert_eq!(names, vec!["Main.java", "Main", "other"]);
+    }
+
+    #[test]
+    fn multi_line_parameter_lists_are_no_overloads() {
+        let code_book = CodeBook { chapters: vec![Chapter {
+            name: String::from("Scanning"),
+            path: None,
+            code_tags: vec![
+                CodeTag { chapter: 0, name: String::from("run"), index: 0, options: CodeTagOptions::default(), exercise: None },
+                CodeTag { chapter: 0, name: String::from("body"), index: 1, options: CodeTagOptions::default(), exercise: None }
+            ]
+        }] };
+        let source_dir = std::env::temp_dir().join(format!("codetags-params-{}", std::process::id()));
+        std::fs::create_dir_all(&source_dir).unwrap();
+        let path = source_dir.join("Main.java");
+        std::fs::write(&path, "//> Scanning run\nclass Main {\n  void run(int a,\n      int b) {\n//> Scanning body\n    int c = a + b;\n//< Scanning body\n  }\n}\n").unwrap();
+
+        let config = Configuration::default();
+        let mut parser = SourceFileParser::new(&code_book, &config);
+        let source_files = vec![parser.parse_source_file(&path, &source_dir).unwrap()];
+        std::fs::remove_dir_all(&source_dir).unwrap();
+
+        let pre = CodeTagsHighlighterPreprocessor;
+        let snippets = pre.build_snippets(&source_files);
+        let snippet = pre.find_snippet(&code_book, &snippets, "Scanning", "body").unwrap();
+        assert_eq!(snippet.location.as_ref().and_then(|l| l.signature.as_deref()), Some("int a, int b"));
+        assert!(snippet.overloaded.is_empty());
+    }
+
+    #[test]
+    fn code_tag_options_may_quote_parentheses() {
+        let codetag_re = Regex::new(CODETAG_RE_STR).unwrap();
+        let c = codetag_re.captures("^code main (title = \"Main (entry)\", 2 before)\n").unwrap();
+        assert_eq!(&c[1], "main");
+        assert_eq!(&c[2], "title = \"Main (entry)\", 2 before");
+        assert!(codetag_re.captures("^code main\n").unwrap().get(2).is_none());
+    }
+}
@@ -0,0 +1,351 @@
+use crate::{config::{Configuration, LatexPackage}, options::FileOptions, preprocessor::{Caption, CodeTag, LineKind, Snippet, SourceFile}};
+
+/// Turns the snippets of the directives into the output of an mdbook renderer.
+///
+/// The preprocessor picks one of the built-in implementations from the name
+/// of the renderer, see [`renderer_for`]. Other ones can be used through
+/// [`CodeTagsHighlighterPreprocessor::render_book`](crate::CodeTagsHighlighterPreprocessor::render_book).
+pub trait SnippetRenderer {
+    /// Renders the snippet of a `^code` directive, identified by `anchor`.
+    /// `caption` holds the parts of its location caption, `None` when hidden.
+    fn render_snippet(&self, snippet: &Snippet, anchor: &str, caption: Option<&Caption>, config: &Configuration, output: &mut String);
+
+    /// Renders the added lines of the snippet of a `^ref` directive, which
+    /// comes from the chapter `chapter_name` and is linked to by `link`.
+    fn render_reference(&self, snippet: &Snippet, chapter_name: &str, link: &str, output: &mut String);
+
+    /// Renders the whole source file of a `^file` directive as it stands at `point`.
+    fn render_file(&self, source_file: &SourceFile, point: &CodeTag, options: &FileOptions, output: &mut String);
+
+    /// Renders the message replacing a directive which could not be resolved.
+    fn render_message(&self, message: &str, output: &mut String) {
+        output.push_str(message);
+        output.push_str("\n\n");
+    }
+}
+
+/// The built-in renderer for the mdbook renderer `name`, if any.
+pub fn renderer_for(name: &str, config: &Configuration) -> Option<Box<dyn SnippetRenderer>> {
+    match name {
+        "html" => Some(Box::new(HtmlRenderer)),
+        "markdown" => Some(Box::new(MarkdownRenderer)),
+        "latex" => Some(Box::new(LatexRenderer { package: config.latex_package })),
+        _ => None
+    }
+}
+
+fn marker(kind: LineKind, no_diff: bool) -> &'static str {
+    match kind {
+        _ if no_diff => "",
+        LineKind::Context => "  ",
+        LineKind::Removed => "- ",
+        LineKind::Added | LineKind::Highlighted => "+ "
+    }
+}
+
+fn is_added(kind: LineKind) -> bool {
+    matches!(kind, LineKind::Added | LineKind::Highlighted)
+}
+
+/// Escapes the characters of `text` which HTML would take for markup.
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// Renders snippets as `<pre>` blocks for the html renderer.
+pub struct HtmlRenderer;
+
+impl SnippetRenderer for HtmlRenderer {
+    fn render_snippet(&self, snippet: &Snippet, anchor: &str, caption: Option<&Caption>, config: &Configuration, output: &mut String) {
+        let options = &snippet.code_tag.options;
+        if options.solution {
+            let summary = options.title.as_deref().unwrap_or("Solution");
+            output.push_str(format!("<details class=\"solution\"><summary>{}</summary>\n", escape_html(summary)).as_str());
+        } else if options.collapsed {
+            let summary = options.title.as_deref().unwrap_or(&snippet.code_tag.name);
+            output.push_str(format!("<details><summary>{}</summary>\n", escape_html(summary)).as_str());
+        } else if let Some(title) = &options.title {
+            output.push_str(format!("<div class=\"title\">{}</div>\n", escape_html(title)).as_str());
+        }
+
+        let dedent = snippet.dedent_width();
+        if dedent > 0 {
+            // Hint that the code is shifted from where it stands in the file.
+            output.push_str(format!("<pre id=\"{}\" class=\"dedented\" title=\"Indented by {} columns in the source\">", anchor, dedent).as_str());
+        } else {
+            output.push_str(format!("<pre id=\"{}\">", anchor).as_str());
+        }
+        if config.permalinks {
+            output.push_str(format!("<a class=\"permalink\" href=\"#{}\" title=\"Link to this snippet\">#</a>", anchor).as_str());
+        }
+        output.push_str(format!("<code class=\"language-{}\">", options.language.as_deref().unwrap_or("java")).as_str());
+        for (kind, line) in snippet.display_lines() {
+            if kind == LineKind::Highlighted {
+                output.push_str("<mark>");
+            }
+            output.push_str(marker(kind, options.no_diff));
+            output.push_str(line);
+            if kind == LineKind::Highlighted {
+                output.push_str("</mark>");
+            }
+            output.push('\n');
+        }
+        output.push_str("</code>\n");
+        if let Some(caption) = caption {
+            let caption = caption.format(|text| String::from(text), |name| format!("<em>{}</em>", name));
+            output.push_str(format!("<div class=\"location\">{}</div>\n", caption).as_str());
+        }
+        output.push_str("</pre>\n");
+        if options.collapsed || options.solution {
+            output.push_str("</details>\n");
+        }
+    }
+
+    fn render_reference(&self, snippet: &Snippet, chapter_name: &str, link: &str, output: &mut String) {
+        let options = &snippet.code_tag.options;
+        output.push_str("<pre class=\"reference\">");
+        output.push_str(format!("<code class=\"language-{}\">", options.language.as_deref().unwrap_or("java")).as_str());
+        for (_, line) in snippet.display_lines().into_iter().filter(|(kind, _)| is_added(*kind)) {
+            output.push_str(line);
+            output.push('\n');
+        }
+        output.push_str("</code>\n");
+        output.push_str(format!("<div class=\"location\">from <a href=\"{}\">{}</a></div>\n", link, escape_html(chapter_name)).as_str());
+        output.push_str("</pre>\n");
+    }
+
+    fn render_file(&self, source_file: &SourceFile, point: &CodeTag, options: &FileOptions, output: &mut String) {
+        if let Some(title) = &options.title {
+            output.push_str(format!("<div class=\"title\">{}</div>\n", escape_html(title)).as_str());
+        }
+        output.push_str("<pre class=\"file\">");
+        output.push_str(format!("<code class=\"language-{}\">", options.language.as_deref().unwrap_or("java")).as_str());
+        for line in source_file.lines.iter().filter(|l| l.is_present_at(point)) {
+            let highlighted = options.highlight_chapter && line.start.chapter == point.chapter;
+            if highlighted {
+                output.push_str("<mark>");
+            }
+            output.push_str(&line.content);
+            if highlighted {
+                output.push_str("</mark>");
+            }
+            output.push('\n');
+        }
+        output.push_str("</code>\n");
+        output.push_str(format!("<div class=\"location\"><em>{}</em></div>\n", source_file.path.display()).as_str());
+        output.push_str("</pre>\n");
+    }
+
+    fn render_message(&self, message: &str, output: &mut String) {
+        output.push_str(format!("<p>{}</p>\n", message).as_str());
+    }
+}
+
+/// Renders snippets as fenced code blocks for the markdown renderer.
+pub struct MarkdownRenderer;
+
+impl MarkdownRenderer {
+    fn fence(&self, language: Option<&str>, output: &mut String) {
+        output.push_str(format!("```{}\n", language.unwrap_or("java")).as_str());
+    }
+}
+
+impl SnippetRenderer for MarkdownRenderer {
+    fn render_snippet(&self, snippet: &Snippet, anchor: &str, caption: Option<&Caption>, _config: &Configuration, output: &mut String) {
+        let options = &snippet.code_tag.options;
+        output.push_str(format!("<a id=\"{}\"></a>\n\n", anchor).as_str());
+        if options.solution || options.collapsed {
+            let default = if options.solution { "Solution" } else { &snippet.code_tag.name };
+            output.push_str(format!("<details><summary>{}</summary>\n\n", options.title.as_deref().unwrap_or(default)).as_str());
+        } else if let Some(title) = &options.title {
+            output.push_str(format!("**{}**\n\n", title).as_str());
+        }
+        self.fence(options.language.as_deref(), output);
+        for (kind, line) in snippet.display_lines() {
+            output.push_str(marker(kind, options.no_diff));
+            output.push_str(line);
+            output.push('\n');
+        }
+        output.push_str("```\n\n");
+        if let Some(caption) = caption {
+            output.push_str(format!("{}\n\n", caption.format(|text| String::from(text), |name| format!("*{}*", name))).as_str());
+        }
+        if options.collapsed || options.solution {
+            output.push_str("</details>\n\n");
+        }
+    }
+
+    fn render_reference(&self, snippet: &Snippet, chapter_name: &str, link: &str, output: &mut String) {
+        self.fence(snippet.code_tag.options.language.as_deref(), output);
+        for (_, line) in snippet.display_lines().into_iter().filter(|(kind, _)| is_added(*kind)) {
+            output.push_str(line);
+            output.push('\n');
+        }
+        output.push_str("```\n\n");
+        output.push_str(format!("from [{}]({})\n\n", chapter_name, link).as_str());
+    }
+
+    fn render_file(&self, source_file: &SourceFile, point: &CodeTag, options: &FileOptions, output: &mut String) {
+        if let Some(title) = &options.title {
+            output.push_str(format!("**{}**\n\n", title).as_str());
+        }
+        self.fence(options.language.as_deref(), output);
+        for line in source_file.lines.iter().filter(|l| l.is_present_at(point)) {
+            output.push_str(&line.content);
+            output.push('\n');
+        }
+        output.push_str("```\n\n");
+        output.push_str(format!("*{}*\n\n", source_file.path.display()).as_str());
+    }
+}
+
+/// Escapes the characters which LaTeX treats specially.
+fn escape_latex(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => result.push_str("\\textbackslash{}"),
+            '~' => result.push_str("\\textasciitilde{}"),
+            '^' => result.push_str("\\textasciicircum{}"),
+            '{' | '}' | '$' | '&' | '#' | '_' | '%' => {
+                result.push('\\');
+                result.push(c);
+            }
+            c => result.push(c)
+        }
+    }
+    result
+}
+
+fn caption_to_latex(caption: &Caption) -> String {
+    caption.format(escape_latex, |name| format!("\\emph{{{}}}", escape_latex(name)))
+}
+
+/// Renders snippets as raw LaTeX `lstlisting` or `minted` environments, for
+/// the renderers which convert the Markdown of the book to LaTeX.
+///
+/// Added and removed lines are told apart by the colors `codetagsadded` and
+/// `codetagsremoved`, which the preamble of the document must define, while
+/// location captions go in the margin.
+pub struct LatexRenderer {
+    pub package: LatexPackage
+}
+
+/// Where to escape to LaTeX within a `lstlisting` environment.
+const LISTINGS_ESCAPE: (&str, &str) = ("(*@", "@*)");
+
+impl LatexRenderer {
+    /// Opens the environment of a code block, in which the lines numbered in
+    /// `marked` are emphasized where the package allows it.
+    fn begin(&self, language: Option<&str>, marked: &[usize], output: &mut String) {
+        let language = language.unwrap_or("java");
+        match self.package {
+            LatexPackage::Listings => {
+                output.push_str(format!("\\begin{{lstlisting}}[language={{{}}}, escapeinside={{{}}}{{{}}}]\n",
+                    language, LISTINGS_ESCAPE.0, LISTINGS_ESCAPE.1).as_str());
+            }
+            LatexPackage::Minted if marked.is_empty() => {
+                output.push_str(format!("\\begin{{minted}}{{{}}}\n", language).as_str());
+            }
+            LatexPackage::Minted => {
+                let lines: Vec<String> = marked.iter().map(|l| l.to_string()).collect();
+                output.push_str(format!("\\begin{{minted}}[highlightlines={{{}}}, highlightcolor=codetagsadded]{{{}}}\n",
+                    lines.join(","), language).as_str());
+            }
+        }
+    }
+
+    fn end(&self, output: &mut String) {
+        output.push_str(match self.package {
+            LatexPackage::Listings => "\\end{lstlisting}\n",
+            LatexPackage::Minted => "\\end{minted}\n"
+        });
+    }
+
+    /// The diff marker of a line. Only listings can color it, minted colors
+    /// the background of the added lines instead.
+    fn marker(&self, kind: LineKind, no_diff: bool) -> String {
+        let color = match kind {
+            LineKind::Removed => "codetagsremoved",
+            LineKind::Added | LineKind::Highlighted => "codetagsadded",
+            LineKind::Context => return String::from(marker(kind, no_diff))
+        };
+        match self.package {
+            LatexPackage::Listings if !no_diff => format!("{}\\textcolor{{{}}}{{{}}}{} ",
+                LISTINGS_ESCAPE.0, color, marker(kind, no_diff).trim(), LISTINGS_ESCAPE.1),
+            _ => String::from(marker(kind, no_diff))
+        }
+    }
+
+    fn margin_note(&self, text: &str, output: &mut String) {
+        output.push_str(format!("\\marginpar{{\\footnotesize {}}}\n", text).as_str());
+    }
+}
+
+impl SnippetRenderer for LatexRenderer {
+    fn render_snippet(&self, snippet: &Snippet, anchor: &str, caption: Option<&Caption>, _config: &Configuration, output: &mut String) {
+        let options = &snippet.code_tag.options;
+        output.push_str(format!("\\label{{{}}}\n", anchor).as_str());
+        if let Some(title) = &options.title {
+            output.push_str(format!("\\textbf{{{}}}\n", escape_latex(title)).as_str());
+        } else if options.solution {
+            output.push_str("\\textbf{Solution}\n");
+        }
+        let lines = snippet.display_lines();
+        let added: Vec<usize> = lines.iter().enumerate()
+            .filter(|(_, (kind, _))| is_added(*kind))
+            .map(|(index, _)| index + 1)
+            .collect();
+        self.begin(options.language.as_deref(), &added, output);
+        for (kind, line) in lines {
+            output.push_str(&self.marker(kind, options.no_diff));
+            output.push_str(line);
+            output.push('\n');
+        }
+        self.end(output);
+        if let Some(caption) = caption {
+            self.margin_note(&caption_to_latex(caption), output);
+        }
+        output.push('\n');
+    }
+
+    fn render_reference(&self, snippet: &Snippet, chapter_name: &str, _link: &str, output: &mut String) {
+        self.begin(snippet.code_tag.options.language.as_deref(), &[], output);
+        for (_, line) in snippet.display_lines().into_iter().filter(|(kind, _)| is_added(*kind)) {
+            output.push_str(line);
+            output.push('\n');
+        }
+        self.end(output);
+        self.margin_note(&format!("from \\emph{{{}}}", escape_latex(chapter_name)), output);
+        output.push('\n');
+    }
+
+    fn render_file(&self, source_file: &SourceFile, point: &CodeTag, options: &FileOptions, output: &mut String) {
+        if let Some(title) = &options.title {
+            output.push_str(format!("\\textbf{{{}}}\n", escape_latex(title)).as_str());
+        }
+        let lines: Vec<_> = source_file.lines.iter().filter(|l| l.is_present_at(point)).collect();
+        let marked: Vec<usize> = lines.iter().enumerate()
+            .filter(|(_, l)| options.highlight_chapter && l.start.chapter == point.chapter)
+            .map(|(index, _)| index + 1)
+            .collect();
+        self.begin(options.language.as_deref(), &marked, output);
+        for line in lines {
+            output.push_str(&line.content);
+            output.push('\n');
+        }
+        self.end(output);
+        self.margin_note(&format!("\\emph{{{}}}", escape_latex(&source_file.path.display().to_string())), output);
+        output.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn html_titles_are_escaped() {
+        assert_eq!(escape_html("T <x> & y"), "T &lt;x&gt; &amp; y");
+    }
+}